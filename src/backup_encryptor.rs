@@ -0,0 +1,527 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use git2::{Commit, ObjectType, Oid, Repository, Tree};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::{
+    backup_message,
+    utilities::{file_system, git2_api_wrapper, secret_manager},
+};
+
+//Written into the cipher trailer of the backups along with the key derivation
+const CIPHER_NAME: &str = "chacha20-poly1305";
+const KDF_NAME: &str = "argon2id";
+const NONCE_SIZE: usize = 12;
+const SALT_SIZE: usize = 16;
+const CACHE_FOLDER_NAME: &str = "commitpal";
+//The salt is kept per repository, so the key and the encryption cache stay the same between backups
+const SALT_FILE_NAME: &str = "encryption-salt";
+
+//How the key of a backup is derived from the passphrase, e.g.
+//chacha20-poly1305; kdf=argon2id; m=19456; t=2; p=1; salt=<base64>
+struct KeyDerivation {
+    params: Params,
+    salt: Vec<u8>,
+}
+
+impl KeyDerivation {
+    //The salt of the repository, a new one is stored the first time
+    fn load(repo: &Repository) -> Result<KeyDerivation, git2::Error> {
+        let path = repo.path().join(CACHE_FOLDER_NAME).join(SALT_FILE_NAME);
+        let stored_salt = fs::read_to_string(&path)
+            .ok()
+            .and_then(|salt| URL_SAFE_NO_PAD.decode(salt.trim()).ok())
+            .filter(|salt| salt.len() == SALT_SIZE);
+
+        let salt = match stored_salt {
+            Some(salt) => salt,
+            None => {
+                let mut salt = vec![0; SALT_SIZE];
+                OsRng.fill_bytes(&mut salt);
+                let result = match path.parent() {
+                    Some(folder) => fs::create_dir_all(folder).map_err(|e| e.to_string()),
+                    None => Ok(()),
+                }
+                .and_then(|_| {
+                    file_system::write_string_to_file(
+                        &path.to_string_lossy(),
+                        URL_SAFE_NO_PAD.encode(&salt),
+                    )
+                });
+                if let Err(e) = result {
+                    return Err(git2::Error::from_str(&format!(
+                        "Failed to store the encryption salt {}: {}",
+                        path.display(),
+                        e
+                    )));
+                }
+                salt
+            }
+        };
+
+        Ok(KeyDerivation {
+            params: Params::default(),
+            salt,
+        })
+    }
+
+    fn from_trailer(trailer: &str) -> Result<KeyDerivation, git2::Error> {
+        let mut parts = trailer.split(';').map(|part| part.trim());
+        if parts.next() != Some(CIPHER_NAME) {
+            return Err(git2::Error::from_str(&format!(
+                "the cipher {} is not supported",
+                trailer
+            )));
+        }
+
+        let values: HashMap<&str, &str> = parts.filter_map(|part| part.split_once('=')).collect();
+        if values.get("kdf") != Some(&KDF_NAME) {
+            return Err(git2::Error::from_str(&format!(
+                "the key derivation of {} is not supported",
+                trailer
+            )));
+        }
+
+        let get_number = |name: &str| -> Result<u32, git2::Error> {
+            match values.get(name).and_then(|value| value.parse().ok()) {
+                Some(value) => Ok(value),
+                None => Err(git2::Error::from_str(&format!(
+                    "the cipher trailer has no valid {}",
+                    name
+                ))),
+            }
+        };
+        let params = match Params::new(get_number("m")?, get_number("t")?, get_number("p")?, None) {
+            Ok(params) => params,
+            Err(e) => return Err(git2::Error::from_str(&format!("{}", e))),
+        };
+
+        let salt = match values.get("salt").map(|salt| URL_SAFE_NO_PAD.decode(salt)) {
+            Some(Ok(salt)) => salt,
+            _ => {
+                return Err(git2::Error::from_str(
+                    "the cipher trailer has no valid salt",
+                ))
+            }
+        };
+
+        Ok(KeyDerivation { params, salt })
+    }
+
+    fn to_trailer(&self) -> String {
+        format!(
+            "{}; kdf={}; m={}; t={}; p={}; salt={}",
+            CIPHER_NAME,
+            KDF_NAME,
+            self.params.m_cost(),
+            self.params.t_cost(),
+            self.params.p_cost(),
+            URL_SAFE_NO_PAD.encode(&self.salt)
+        )
+    }
+
+    fn derive_key(&self, passphrase: &str) -> Result<Key, git2::Error> {
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone());
+        let mut key = Key::default();
+        match argon2.hash_password_into(passphrase.as_bytes(), &self.salt, &mut key) {
+            Ok(_) => Ok(key),
+            Err(e) => Err(git2::Error::from_str(&format!(
+                "Failed to derive the encryption key: {}",
+                e
+            ))),
+        }
+    }
+}
+
+fn get_key(key_derivation: &KeyDerivation) -> Result<Key, git2::Error> {
+    match secret_manager::get_encryption_key() {
+        Ok(passphrase) => key_derivation.derive_key(&passphrase),
+        Err(e) => Err(git2::Error::from_str(&format!(
+            "Failed to get the encryption key: {}",
+            e
+        ))),
+    }
+}
+
+//The snapshot is not a parent of the encrypted backup, so nothing in plain text is pushed along with it
+//The previous rolling backup is fetched from the remote, linking it uploads nothing new
+pub fn encrypt_snapshot(
+    repo: &Repository,
+    snapshot: &Commit,
+    source_branch: &str,
+    previous_backup: Option<&Commit>,
+    obfuscate_paths: bool,
+) -> Result<Oid, git2::Error> {
+    let (base, _) = git2_api_wrapper::get_backup_parts(snapshot)?;
+    encrypt_commit(
+        repo,
+        snapshot,
+        source_branch,
+        base.id(),
+        previous_backup,
+        obfuscate_paths,
+    )
+}
+
+//The files of the commit in a new commit without history, e.g. the tip of a branch or a stash entry
+//The base is the commit the files are restored onto
+pub fn encrypt_commit(
+    repo: &Repository,
+    commit: &Commit,
+    source_branch: &str,
+    base: Oid,
+    previous_backup: Option<&Commit>,
+    obfuscate_paths: bool,
+) -> Result<Oid, git2::Error> {
+    let key_derivation = KeyDerivation::load(repo)?;
+    let key = get_key(&key_derivation)?;
+    let cipher = ChaCha20Poly1305::new(&key);
+    let mut cache = EncryptionCache::load(repo, &key);
+    let tree_id = encrypt_tree(repo, &commit.tree()?, &cipher, obfuscate_paths, &mut cache)?;
+    cache.save();
+
+    let message = backup_message::build_encrypted_backup_message(
+        source_branch,
+        base,
+        obfuscate_paths,
+        &key_derivation.to_trailer(),
+    );
+
+    let parents: Vec<&Commit> = previous_backup.into_iter().collect();
+
+    let author = commit.author();
+    git2_api_wrapper::commit_all_changes(repo, tree_id, &parents, &message, &author)
+}
+
+//Returns the plain tree of an encrypted backup, it is only written to the local object database
+pub fn decrypt_backup(repo: &Repository, backup: &Commit) -> Result<Oid, git2::Error> {
+    let message = backup.message().unwrap_or_default();
+    let obfuscated_paths =
+        match backup_message::get_trailer(message, backup_message::ENCRYPTED_TRAILER) {
+            Some(encrypted) => encrypted.contains("paths"),
+            None => {
+                return Err(git2::Error::from_str(&format!(
+                    "{} is not an encrypted backup",
+                    backup.id()
+                )))
+            }
+        };
+
+    let key_derivation = match backup_message::get_trailer(message, backup_message::CIPHER_TRAILER)
+    {
+        Some(trailer) => KeyDerivation::from_trailer(trailer),
+        None => Err(git2::Error::from_str("it has no cipher trailer")),
+    };
+    let key_derivation = match key_derivation {
+        Ok(key_derivation) => key_derivation,
+        Err(e) => {
+            return Err(git2::Error::from_str(&format!(
+                "{} cannot be decrypted: {}",
+                backup.id(),
+                e.message()
+            )))
+        }
+    };
+    let cipher = ChaCha20Poly1305::new(&get_key(&key_derivation)?);
+
+    decrypt_tree(repo, &backup.tree()?, &cipher, obfuscated_paths)
+}
+
+//Submodules are kept as they are, they only point to a commit
+//Blobs and trees that were encrypted before are taken from the cache, so only the changes are encrypted
+fn encrypt_tree(
+    repo: &Repository,
+    tree: &Tree,
+    cipher: &ChaCha20Poly1305,
+    obfuscate_paths: bool,
+    cache: &mut EncryptionCache,
+) -> Result<Oid, git2::Error> {
+    let tree_kind = if obfuscate_paths { 'p' } else { 't' };
+    if let Some(id) = cache.get(repo, tree_kind, tree.id()) {
+        return Ok(id);
+    }
+
+    let mut builder = repo.treebuilder(None)?;
+
+    for entry in tree.iter() {
+        let id = match entry.kind() {
+            Some(ObjectType::Tree) => encrypt_tree(
+                repo,
+                &repo.find_tree(entry.id())?,
+                cipher,
+                obfuscate_paths,
+                cache,
+            )?,
+            Some(ObjectType::Blob) => match cache.get(repo, 'b', entry.id()) {
+                Some(id) => id,
+                None => {
+                    let blob = repo.find_blob(entry.id())?;
+                    let id = repo.blob(&encrypt_bytes(cipher, blob.content())?)?;
+                    cache.insert('b', entry.id(), id);
+                    id
+                }
+            },
+            _ => entry.id(),
+        };
+
+        let name = String::from_utf8_lossy(entry.name_bytes()).to_string();
+        let name = if obfuscate_paths {
+            //The url safe alphabet has no slash, which is not allowed in a file name
+            URL_SAFE_NO_PAD.encode(encrypt_bytes(cipher, name.as_bytes())?)
+        } else {
+            name
+        };
+
+        builder.insert(name, id, entry.filemode())?;
+    }
+
+    let id = builder.write()?;
+    cache.insert(tree_kind, tree.id(), id);
+    Ok(id)
+}
+
+fn decrypt_tree(
+    repo: &Repository,
+    tree: &Tree,
+    cipher: &ChaCha20Poly1305,
+    obfuscated_paths: bool,
+) -> Result<Oid, git2::Error> {
+    let mut builder = repo.treebuilder(None)?;
+
+    for entry in tree.iter() {
+        let id = match entry.kind() {
+            Some(ObjectType::Tree) => {
+                decrypt_tree(repo, &repo.find_tree(entry.id())?, cipher, obfuscated_paths)?
+            }
+            Some(ObjectType::Blob) => {
+                let blob = repo.find_blob(entry.id())?;
+                repo.blob(&decrypt_bytes(cipher, blob.content())?)?
+            }
+            _ => entry.id(),
+        };
+
+        let name = String::from_utf8_lossy(entry.name_bytes()).to_string();
+        let name = if obfuscated_paths {
+            decrypt_name(cipher, &name)?
+        } else {
+            name
+        };
+
+        builder.insert(name, id, entry.filemode())?;
+    }
+
+    builder.write()
+}
+
+//A random nonce per call, stored in front of the ciphertext
+fn encrypt_bytes(cipher: &ChaCha20Poly1305, content: &[u8]) -> Result<Vec<u8>, git2::Error> {
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    match cipher.encrypt(&nonce, content) {
+        Ok(encrypted) => Ok([nonce.as_slice(), &encrypted].concat()),
+        Err(e) => Err(git2::Error::from_str(&format!("Failed to encrypt: {}", e))),
+    }
+}
+
+fn decrypt_bytes(cipher: &ChaCha20Poly1305, content: &[u8]) -> Result<Vec<u8>, git2::Error> {
+    if content.len() < NONCE_SIZE {
+        return Err(decryption_error("the content is too short"));
+    }
+
+    let (nonce, encrypted) = content.split_at(NONCE_SIZE);
+    match cipher.decrypt(Nonce::from_slice(nonce), encrypted) {
+        Ok(content) => Ok(content),
+        Err(e) => Err(decryption_error(&e.to_string())),
+    }
+}
+
+fn decrypt_name(cipher: &ChaCha20Poly1305, name: &str) -> Result<String, git2::Error> {
+    let encrypted = match URL_SAFE_NO_PAD.decode(name) {
+        Ok(encrypted) => encrypted,
+        Err(e) => return Err(decryption_error(&e.to_string())),
+    };
+
+    match String::from_utf8(decrypt_bytes(cipher, &encrypted)?) {
+        Ok(name) => Ok(name),
+        Err(e) => Err(decryption_error(&e.to_string())),
+    }
+}
+
+fn decryption_error(reason: &str) -> git2::Error {
+    git2::Error::from_str(&format!(
+        "Failed to decrypt the backup, the encryption key may be wrong: {}",
+        reason
+    ))
+}
+
+//Maps the plain objects to their encrypted copies, one file per key under the git folder of the repository
+//The kinds are b for blobs, t for trees and p for trees with obfuscated paths
+//The file name is a hash of the key, so a new key starts with an empty cache
+struct EncryptionCache {
+    path: PathBuf,
+    entries: HashMap<(char, Oid), Oid>,
+    is_changed: bool,
+}
+
+impl EncryptionCache {
+    fn load(repo: &Repository, key: &Key) -> EncryptionCache {
+        let fingerprint: String = Sha256::digest(key)
+            .iter()
+            .take(8)
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let path = repo
+            .path()
+            .join(CACHE_FOLDER_NAME)
+            .join(format!("encryption-cache-{}", fingerprint));
+
+        //A missing or damaged cache only means everything is encrypted again
+        let entries = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split(' ');
+                let kind = parts.next()?.chars().next()?;
+                let plain = Oid::from_str(parts.next()?).ok()?;
+                let encrypted = Oid::from_str(parts.next()?).ok()?;
+                Some(((kind, plain), encrypted))
+            })
+            .collect();
+
+        EncryptionCache {
+            path,
+            entries,
+            is_changed: false,
+        }
+    }
+
+    //The encrypted object may have been removed, e.g. by git gc after a failed push
+    fn get(&self, repo: &Repository, kind: char, plain: Oid) -> Option<Oid> {
+        let encrypted = *self.entries.get(&(kind, plain))?;
+        let odb = repo.odb().ok()?;
+        odb.exists(encrypted).then_some(encrypted)
+    }
+
+    fn insert(&mut self, kind: char, plain: Oid, encrypted: Oid) {
+        self.entries.insert((kind, plain), encrypted);
+        self.is_changed = true;
+    }
+
+    //The backup does not depend on the cache, so failing to store it is only reported
+    fn save(&self) {
+        if !self.is_changed {
+            return;
+        }
+
+        let content: String = self
+            .entries
+            .iter()
+            .map(|((kind, plain), encrypted)| format!("{} {} {}\n", kind, plain, encrypted))
+            .collect();
+        let result = match self.path.parent() {
+            Some(folder) => fs::create_dir_all(folder).map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+        .and_then(|_| file_system::write_string_to_file(&self.path.to_string_lossy(), content));
+
+        if let Err(e) = result {
+            println!(
+                "Failed to store the encryption cache {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher() -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&[7; 32]))
+    }
+
+    #[test]
+    fn encrypted_bytes_round_trip_with_a_new_nonce_each_time() {
+        let cipher = cipher();
+        let first = encrypt_bytes(&cipher, b"secret content").unwrap();
+        let second = encrypt_bytes(&cipher, b"secret content").unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(decrypt_bytes(&cipher, &first).unwrap(), b"secret content");
+        assert_eq!(decrypt_bytes(&cipher, &second).unwrap(), b"secret content");
+    }
+
+    #[test]
+    fn tampered_or_foreign_content_is_rejected() {
+        let cipher = cipher();
+        let mut encrypted = encrypt_bytes(&cipher, b"secret content").unwrap();
+        let last = encrypted.len() - 1;
+        encrypted[last] ^= 1;
+
+        assert!(decrypt_bytes(&cipher, &encrypted).is_err());
+        assert!(decrypt_bytes(&cipher, b"short").is_err());
+
+        let other_cipher = ChaCha20Poly1305::new(Key::from_slice(&[8; 32]));
+        let encrypted = encrypt_bytes(&cipher, b"secret content").unwrap();
+        assert!(decrypt_bytes(&other_cipher, &encrypted).is_err());
+    }
+
+    #[test]
+    fn keys_are_derived_again_from_the_cipher_trailer() {
+        let key_derivation = KeyDerivation {
+            params: Params::new(64, 1, 1, None).unwrap(),
+            salt: vec![1; SALT_SIZE],
+        };
+        let trailer = key_derivation.to_trailer();
+        assert!(trailer.starts_with("chacha20-poly1305; kdf=argon2id; m=64; t=1; p=1; salt="));
+
+        let parsed = KeyDerivation::from_trailer(&trailer).unwrap();
+        assert_eq!(parsed.salt, key_derivation.salt);
+        let key = key_derivation.derive_key("passphrase").unwrap();
+        assert_eq!(parsed.derive_key("passphrase").unwrap(), key);
+        assert_ne!(parsed.derive_key("other passphrase").unwrap(), key);
+
+        let other_salt = KeyDerivation {
+            params: Params::new(64, 1, 1, None).unwrap(),
+            salt: vec![2; SALT_SIZE],
+        };
+        assert_ne!(other_salt.derive_key("passphrase").unwrap(), key);
+
+        assert!(KeyDerivation::from_trailer("chacha20-poly1305").is_err());
+        assert!(KeyDerivation::from_trailer("aes-256-cbc; kdf=argon2id").is_err());
+    }
+
+    #[test]
+    fn unchanged_objects_are_encrypted_once() {
+        let folder = tempfile::tempdir().unwrap();
+        let repo = Repository::init(folder.path()).unwrap();
+        let key = Key::clone_from_slice(&[7; 32]);
+        let cipher = cipher();
+
+        let mut builder = repo.treebuilder(None).unwrap();
+        let blob_id = repo.blob(b"content").unwrap();
+        builder.insert("file.txt", blob_id, 0o100644).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+
+        let mut cache = EncryptionCache::load(&repo, &key);
+        let encrypted = encrypt_tree(&repo, &tree, &cipher, true, &mut cache).unwrap();
+        cache.save();
+
+        let mut cache = EncryptionCache::load(&repo, &key);
+        assert!(cache.get(&repo, 'b', blob_id).is_some());
+        let again = encrypt_tree(&repo, &tree, &cipher, true, &mut cache).unwrap();
+        assert_eq!(encrypted, again);
+
+        let decrypted =
+            decrypt_tree(&repo, &repo.find_tree(encrypted).unwrap(), &cipher, true).unwrap();
+        assert_eq!(decrypted, tree.id());
+    }
+}
@@ -1,29 +1,60 @@
+use crate::data_structures::{
+    backup_ref::BackupRef,
+    config::{
+        BackupBranchMode, Config, BRANCH_NAME_BRANCH_PLACEHOLDER, BRANCH_NAME_HOST_PLACEHOLDER,
+    },
+};
 use crate::file_change_watcher::FileChangeSignal;
 use crate::repository_instance::RepositoryInstance;
+use crate::shared_config::SharedConfig;
+use crate::snapshot_repo::SnapshotRepo;
 use crate::utilities::notification_service;
-use crate::{config_manager, file_change_watcher};
-use chrono::Local;
+use crate::{backup_message, backup_pruner, file_change_watcher};
+use chrono::{DateTime, Duration, Local, NaiveDateTime, Utc};
+use git2::Oid;
+use regex::Regex;
 use std::collections::{hash_map::Entry::Vacant, HashMap};
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
 use sys_info::hostname;
 
+//Expired backups are pruned at most once per interval, in hours
+const PRUNE_INTERVAL: i64 = 24;
+//Failed pushes are retried after 1, 2, 4... minutes, up to the max, per remote host
+const PUSH_RETRY_BASE_DELAY: i64 = 1;
+const PUSH_RETRY_MAX_DELAY: i64 = 120;
+
+struct PushBackoff {
+    failures: u32,
+    next_attempt_time: DateTime<Utc>,
+}
+
 pub struct BackupExecutor {
     //Has to be a hashset for easy removal
     map: HashMap<String, RepositoryInstance>,
+    last_prune_time: Option<DateTime<Utc>>,
+    push_backoffs: HashMap<String, PushBackoff>,
+    config: SharedConfig,
 }
 
 impl BackupExecutor {
     pub fn new() -> BackupExecutor {
         BackupExecutor {
             map: HashMap::new(),
+            last_prune_time: None,
+            push_backoffs: HashMap::new(),
+            config: SharedConfig::load(),
         }
     }
 
     pub fn start(&mut self) {
         let (tx, rx): (Sender<FileChangeSignal>, Receiver<FileChangeSignal>) = mpsc::channel();
+        let config_updates = self.config.subscribe();
 
-        file_change_watcher::start(tx);
+        file_change_watcher::start(tx, self.config.clone());
         loop {
+            let snapshot = self.config.get();
+            let config = &snapshot.config;
+
             //continue to process messages from the channel until try_recv() returns an error, indicating that the channel is empty.
             while let Ok(signal) = rx.try_recv() {
                 self.update_repo_instance_states(signal);
@@ -40,25 +71,41 @@ impl BackupExecutor {
                 }
             }
 
-            self.update_map();
+            self.update_map(config);
 
             if !self.map.is_empty() {
-                self.backup_check();
+                self.backup_check(config);
+                self.outbox_check(config);
+                self.prune_check(config);
             } else {
                 println!("No repository to watch");
             }
 
+            //Each repository is checked against its own frequency, so wake up for the most frequent one
             let backup_frequency = if cfg!(debug_assertions) {
                 5
             } else {
-                println!(
-                    "Sleeping for {} seconds",
-                    config_manager::read_config().backup_frequency * 60
-                );
-                config_manager::read_config().backup_frequency * 60
+                let backup_frequency = config
+                    .get_active_folders()
+                    .iter()
+                    .map(|folder| config.get_backup_frequency(folder))
+                    .min()
+                    .unwrap_or(config.backup_frequency)
+                    .max(1)
+                    * 60;
+                println!("Sleeping for {} seconds", backup_frequency);
+                backup_frequency
             };
 
-            std::thread::sleep(std::time::Duration::from_secs(backup_frequency));
+            //A config change wakes the executor up, so changes to the watch list take effect immediately
+            match config_updates.recv_timeout(std::time::Duration::from_secs(backup_frequency)) {
+                Ok(_) => while config_updates.try_recv().is_ok() {},
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    println!("Config update channel disconnected");
+                    return;
+                }
+            }
         }
     }
 
@@ -74,10 +121,11 @@ impl BackupExecutor {
         }
     }
 
-    fn update_map(&mut self) {
-        let config = config_manager::read_config();
+    //Paused repositories are left out
+    fn update_map(&mut self, config: &Config) {
+        let active_folders = config.get_active_folders();
 
-        for folder in &config.watching_folders {
+        for folder in &active_folders {
             //Map.entry returns Vacant or Occupied
             if let Vacant(_) = self.map.entry(folder.clone()) {
                 let repo_instance = match RepositoryInstance::new(folder) {
@@ -91,15 +139,14 @@ impl BackupExecutor {
             }
         }
 
-        self.map
-            .retain(|key, _| config.watching_folders.contains(key));
+        self.map.retain(|key, _| active_folders.contains(key));
     }
     //Trade-off noted: this is not a pure function, yet it prevents cloning the map
-    fn backup_check(&mut self) {
+    fn backup_check(&mut self, config: &Config) {
         println!("Performing backup check");
 
         for repo_instance in &mut self.map.values_mut() {
-            match repo_instance.try_perform_backup() {
+            match repo_instance.try_perform_backup(config) {
                 Ok(_) => {}
                 Err(e) => {
                     notification_service::show_notification(
@@ -110,16 +157,351 @@ impl BackupExecutor {
             }
         }
     }
+
+    //Push the pending snapshots of every repository, skipping the remote hosts that are backing off
+    fn outbox_check(&mut self, config: &Config) {
+        if config.local_only {
+            return;
+        }
+
+        for repo_path in self.map.keys() {
+            let snapshot_repo = match SnapshotRepo::with_config(repo_path, config.clone()) {
+                Ok(snapshot_repo) => snapshot_repo,
+                Err(e) => {
+                    println!("Failed to open {}: {}", repo_path, e);
+                    continue;
+                }
+            };
+
+            let remote_host = snapshot_repo.get_remote_host();
+            if let Some(backoff) = self.push_backoffs.get(&remote_host) {
+                if Utc::now() < backoff.next_attempt_time {
+                    println!(
+                        "Skip pushing {}, {} is unreachable until {}",
+                        repo_path, remote_host, backoff.next_attempt_time
+                    );
+                    continue;
+                }
+            }
+
+            let result = snapshot_repo.push_pending_snapshots().and_then(|count| {
+                let branch_count = snapshot_repo.push_unpushed_commits()?;
+                let stash_count = snapshot_repo.push_stashes()?;
+                Ok((count, branch_count, stash_count))
+            });
+
+            match result {
+                Ok((count, branch_count, stash_count)) => {
+                    if count > 0 {
+                        println!("Pushed {} snapshots of {}", count, repo_path);
+                    }
+                    if branch_count > 0 {
+                        println!(
+                            "Pushed the unpushed commits of {} branches of {}",
+                            branch_count, repo_path
+                        );
+                    }
+                    if stash_count > 0 {
+                        println!("Pushed {} stash entries of {}", stash_count, repo_path);
+                    }
+                    self.push_backoffs.remove(&remote_host);
+                }
+                Err(e) => {
+                    let failures = self
+                        .push_backoffs
+                        .get(&remote_host)
+                        .map_or(1, |backoff| backoff.failures + 1);
+                    let delay = PUSH_RETRY_BASE_DELAY
+                        .saturating_mul(1 << (failures - 1).min(16))
+                        .min(PUSH_RETRY_MAX_DELAY);
+                    println!(
+                        "Failed to push {} to {}, retry in {} minutes: {}",
+                        repo_path, remote_host, delay, e
+                    );
+
+                    //Only notify once per outage
+                    if failures == 1 {
+                        notification_service::show_notification(
+                            "Failed to push backup".to_string(),
+                            format!(
+                                "{} is unreachable, the snapshots are kept and will be pushed later: {}",
+                                remote_host, e
+                            ),
+                        );
+                    }
+
+                    self.push_backoffs.insert(
+                        remote_host,
+                        PushBackoff {
+                            failures,
+                            next_attempt_time: Utc::now() + Duration::minutes(delay),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    fn prune_check(&mut self, config: &Config) {
+        if config.retention.is_none() {
+            return;
+        }
+
+        let now = Utc::now();
+        if let Some(last_prune_time) = self.last_prune_time {
+            if now.signed_duration_since(last_prune_time).num_hours() < PRUNE_INTERVAL {
+                return;
+            }
+        }
+        self.last_prune_time = Some(now);
+
+        println!("Pruning expired backups");
+        for repo_path in self.map.keys() {
+            match backup_pruner::prune(repo_path, config, false, false) {
+                Ok(pruned) => println!("Pruned {} backups of {}", pruned.len(), repo_path),
+                Err(e) => println!("Failed to prune the backups of {}: {}", repo_path, e),
+            }
+        }
+    }
+}
+
+pub fn get_host_name() -> String {
+    hostname().unwrap_or("Unknown_host".to_string())
+}
+
+const BACKUP_TIME_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+//Every snapshot is also stored in the repository itself, one reference per source branch
+//The reflog of the reference keeps the history of the snapshots
+const LOCAL_BACKUP_REF_NAMESPACE: &str = "refs/commitpal-local";
+//Points to the latest local snapshot that has been pushed
+const PUSHED_BACKUP_REF_NAMESPACE: &str = "refs/commitpal-pushed";
+//Points to the tip of each local branch whose unpushed commits have been backed up
+const PUSHED_COMMITS_REF_NAMESPACE: &str = "refs/commitpal-pushed-commits";
+//One reference per stash entry that has been backed up, named after its commit id
+const PUSHED_STASHES_REF_NAMESPACE: &str = "refs/commitpal-pushed-stashes";
+
+//The template decides the order of the host and the branch, e.g. refs/commitpal/<host>/<branch>
+pub fn get_back_up_ref_name(
+    current_branch_name: &str,
+    mode: BackupBranchMode,
+    namespace: &str,
+    template: &str,
+    time: DateTime<Local>,
+) -> String {
+    let name = template
+        .replace(BRANCH_NAME_HOST_PLACEHOLDER, &get_host_name())
+        .replace(BRANCH_NAME_BRANCH_PLACEHOLDER, current_branch_name);
+    let namespace = namespace.trim_end_matches('/');
+
+    match mode {
+        BackupBranchMode::Timestamped => {
+            format!("{}/{}_{}", namespace, name, time.format(BACKUP_TIME_FORMAT))
+        }
+        BackupBranchMode::Rolling => format!("{}/{}", namespace, name),
+    }
+}
+
+pub fn get_local_back_up_ref_name(current_branch_name: &str) -> String {
+    format!("{}/{}", LOCAL_BACKUP_REF_NAMESPACE, current_branch_name)
 }
 
-pub fn get_back_up_branch_name(current_branch_name: &str) -> String {
-    let host = hostname().unwrap_or("Unknown_host".to_string());
-    let current_time = Local::now();
+pub fn get_pushed_back_up_ref_name(current_branch_name: &str) -> String {
+    format!("{}/{}", PUSHED_BACKUP_REF_NAMESPACE, current_branch_name)
+}
 
+//The unpushed commits of a branch are kept next to the snapshots, e.g. refs/commitpal-commits/<host>/<branch>
+//A separate namespace keeps them out of the snapshot listing and away from the retention policy
+pub fn get_commit_back_up_ref_name(branch_name: &str, namespace: &str) -> String {
     format!(
-        "backup/{}/{}_{}",
-        host,
-        current_branch_name,
-        current_time.format("%Y-%m-%d_%H-%M-%S")
+        "{}-commits/{}/{}",
+        namespace.trim_end_matches('/'),
+        get_host_name(),
+        branch_name
     )
 }
+
+pub fn get_commit_back_up_prefix(namespace: &str) -> String {
+    format!("{}-commits/", namespace.trim_end_matches('/'))
+}
+
+pub fn get_pushed_commits_ref_name(branch_name: &str) -> String {
+    format!("{}/{}", PUSHED_COMMITS_REF_NAMESPACE, branch_name)
+}
+
+//Reverse of get_pushed_commits_ref_name
+pub fn parse_pushed_commits_ref_name(ref_name: &str) -> Option<&str> {
+    ref_name
+        .strip_prefix(PUSHED_COMMITS_REF_NAMESPACE)?
+        .strip_prefix('/')
+}
+
+//Stash entries are keyed by their commit id, e.g. refs/commitpal-stashes/<host>/<stash id>
+pub fn get_stash_back_up_ref_name(stash_id: Oid, namespace: &str) -> String {
+    format!(
+        "{}-stashes/{}/{}",
+        namespace.trim_end_matches('/'),
+        get_host_name(),
+        stash_id
+    )
+}
+
+pub fn get_stash_back_up_prefix(namespace: &str) -> String {
+    format!("{}-stashes/", namespace.trim_end_matches('/'))
+}
+
+pub fn get_pushed_stash_ref_name(stash_id: Oid) -> String {
+    format!("{}/{}", PUSHED_STASHES_REF_NAMESPACE, stash_id)
+}
+
+//Reverse of get_pushed_stash_ref_name
+pub fn parse_pushed_stash_ref_name(ref_name: &str) -> Option<Oid> {
+    let stash_id = ref_name
+        .strip_prefix(PUSHED_STASHES_REF_NAMESPACE)?
+        .strip_prefix('/')?;
+    Oid::from_str(stash_id).ok()
+}
+
+//Reverse of get_local_back_up_ref_name, None if the reference is not a local backup
+pub fn parse_local_back_up_ref_name(ref_name: &str) -> Option<&str> {
+    ref_name
+        .strip_prefix(LOCAL_BACKUP_REF_NAMESPACE)?
+        .strip_prefix('/')
+}
+
+//Matches the names made by get_back_up_ref_name with the same namespace and template
+pub fn get_back_up_ref_regex(namespace: &str, template: &str) -> Result<Regex, git2::Error> {
+    let name = regex::escape(template)
+        .replacen(
+            &regex::escape(BRANCH_NAME_HOST_PLACEHOLDER),
+            "(?P<host>[^/]+?)",
+            1,
+        )
+        .replacen(
+            &regex::escape(BRANCH_NAME_BRANCH_PLACEHOLDER),
+            "(?P<branch>.+?)",
+            1,
+        );
+
+    //The time suffix contains an underscore itself, e.g. main_2024-03-01_12-30-00
+    let pattern = format!(
+        r"^{}/{}(_(?P<time>\d{{4}}-\d{{2}}-\d{{2}}_\d{{2}}-\d{{2}}-\d{{2}}))?$",
+        regex::escape(namespace.trim_end_matches('/')),
+        name
+    );
+
+    match Regex::new(&pattern) {
+        Ok(regex) => Ok(regex),
+        Err(e) => Err(git2::Error::from_str(&format!(
+            "Invalid branch name template {}: {}",
+            template, e
+        ))),
+    }
+}
+
+//Reverse of get_back_up_ref_name, None if the reference does not match the regex of the namespace and template
+pub fn parse_back_up_ref_name(
+    ref_name: &str,
+    ref_regex: &Regex,
+    commit_id: Oid,
+) -> Option<BackupRef> {
+    let captures = ref_regex.captures(ref_name)?;
+    let created_at = captures
+        .name("time")
+        .and_then(|time| NaiveDateTime::parse_from_str(time.as_str(), BACKUP_TIME_FORMAT).ok());
+
+    Some(BackupRef {
+        ref_name: ref_name.to_string(),
+        host: captures.name("host")?.as_str().to_string(),
+        source_branch: captures.name("branch")?.as_str().to_string(),
+        created_at,
+        commit_id,
+    })
+}
+
+//Reverse of get_back_up_ref_name through the trailers of the backup commit, whatever template named the reference
+//None if the commit is not a snapshot made by CommitPal
+pub fn parse_back_up_commit(ref_name: &str, message: &str, commit_id: Oid) -> Option<BackupRef> {
+    Some(BackupRef {
+        ref_name: ref_name.to_string(),
+        host: backup_message::get_trailer(message, backup_message::HOST_TRAILER)?.to_string(),
+        source_branch: backup_message::get_trailer(message, backup_message::SOURCE_BRANCH_TRAILER)?
+            .to_string(),
+        created_at: parse_back_up_time(ref_name),
+        commit_id,
+    })
+}
+
+//The time suffix of a timestamped backup, e.g. main_2024-03-01_12-30-00
+fn parse_back_up_time(ref_name: &str) -> Option<NaiveDateTime> {
+    let time_length = "2024-03-01_12-30-00".len();
+    let name = ref_name.get(..ref_name.len().checked_sub(time_length)?)?;
+    if !name.ends_with('_') {
+        return None;
+    }
+    NaiveDateTime::parse_from_str(&ref_name[name.len()..], BACKUP_TIME_FORMAT).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_up_ref_names_are_parsed_back() {
+        let regex = get_back_up_ref_regex("refs/heads/backup/", "{host}/{branch}").unwrap();
+        let oid = Oid::zero();
+
+        let rolling =
+            parse_back_up_ref_name("refs/heads/backup/vm/feature/login", &regex, oid).unwrap();
+        assert_eq!(rolling.host, "vm");
+        assert_eq!(rolling.source_branch, "feature/login");
+        assert!(rolling.created_at.is_none());
+
+        let timestamped =
+            parse_back_up_ref_name("refs/heads/backup/vm/main_2024-03-01_12-30-00", &regex, oid)
+                .unwrap();
+        assert_eq!(timestamped.source_branch, "main");
+        assert_eq!(
+            timestamped.created_at,
+            NaiveDateTime::parse_from_str("2024-03-01_12-30-00", BACKUP_TIME_FORMAT).ok()
+        );
+
+        assert!(parse_back_up_ref_name("refs/heads/other/vm/main", &regex, oid).is_none());
+        assert!(parse_back_up_ref_name("refs/heads/backup/main", &regex, oid).is_none());
+    }
+
+    #[test]
+    fn back_up_ref_regex_escapes_the_template() {
+        let regex = get_back_up_ref_regex("refs/backup", "{branch}/at.{host}").unwrap();
+
+        let backup =
+            parse_back_up_ref_name("refs/backup/feature/x/at.vm", &regex, Oid::zero()).unwrap();
+        assert_eq!(backup.source_branch, "feature/x");
+        assert_eq!(backup.host, "vm");
+        assert!(parse_back_up_ref_name("refs/backup/main/atxvm", &regex, Oid::zero()).is_none());
+    }
+
+    #[test]
+    fn back_up_commits_are_parsed_from_their_trailers() {
+        let message = "CommitPal backup of feat_x on my_host (1 files changed)\n\nCommitPal-Host: my_host\nCommitPal-Source-Branch: feat_x\nCommitPal-Base: 0000000000000000000000000000000000000000\n";
+
+        let backup = parse_back_up_commit(
+            "refs/heads/backup/feat_x_my_host_2024-03-01_12-30-00",
+            message,
+            Oid::zero(),
+        )
+        .unwrap();
+        assert_eq!(backup.host, "my_host");
+        assert_eq!(backup.source_branch, "feat_x");
+        assert_eq!(
+            backup.created_at,
+            NaiveDateTime::parse_from_str("2024-03-01_12-30-00", BACKUP_TIME_FORMAT).ok()
+        );
+
+        let rolling =
+            parse_back_up_commit("refs/heads/backup/feat_x_my_host", message, Oid::zero()).unwrap();
+        assert!(rolling.created_at.is_none());
+
+        assert!(parse_back_up_commit("refs/heads/backup/main", "A commit", Oid::zero()).is_none());
+    }
+}
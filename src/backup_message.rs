@@ -0,0 +1,138 @@
+use git2::{Commit, Delta, Oid, Patch, Repository, Tree};
+
+use crate::backup_executor;
+
+//Machine-readable trailers at the end of every backup commit message
+pub const HOST_TRAILER: &str = "CommitPal-Host";
+pub const SOURCE_BRANCH_TRAILER: &str = "CommitPal-Source-Branch";
+pub const BASE_TRAILER: &str = "CommitPal-Base";
+pub const ENCRYPTED_TRAILER: &str = "CommitPal-Encrypted";
+pub const CIPHER_TRAILER: &str = "CommitPal-Cipher";
+
+//Long change lists are cut, the total is still reported
+const MAX_LISTED_FILES: usize = 50;
+
+//A summary line, the base commit, a diffstat-style list of the changed files and the trailers
+pub fn build_backup_message(
+    repo: &Repository,
+    source_branch: &str,
+    base: &Commit,
+    tree: &Tree,
+) -> Result<String, git2::Error> {
+    let host = backup_executor::get_host_name();
+    let changes = get_changed_files(repo, &base.tree()?, tree)?;
+
+    let mut message = format!(
+        "CommitPal backup of {} on {} ({} files changed)\n\n",
+        source_branch,
+        host,
+        changes.len()
+    );
+
+    message.push_str(&format!(
+        "Base: {} {}\n",
+        base.id(),
+        base.summary().unwrap_or_default()
+    ));
+
+    if !changes.is_empty() {
+        message.push('\n');
+        for change in changes.iter().take(MAX_LISTED_FILES) {
+            message.push_str(&format!("{}\n", change));
+        }
+        if changes.len() > MAX_LISTED_FILES {
+            message.push_str(&format!(
+                "... and {} more\n",
+                changes.len() - MAX_LISTED_FILES
+            ));
+        }
+    }
+
+    message.push_str(&format!("\n{}: {}\n", HOST_TRAILER, host));
+    message.push_str(&format!("{}: {}\n", SOURCE_BRANCH_TRAILER, source_branch));
+    message.push_str(&format!("{}: {}\n", BASE_TRAILER, base.id()));
+
+    Ok(message)
+}
+
+//The file list would reveal the paths, so only the trailers are kept
+//The cipher trailer tells how the key is derived again at restore time
+pub fn build_encrypted_backup_message(
+    source_branch: &str,
+    base: Oid,
+    obfuscate_paths: bool,
+    cipher: &str,
+) -> String {
+    let host = backup_executor::get_host_name();
+    let mut message = format!(
+        "CommitPal encrypted backup of {} on {}\n\n",
+        source_branch, host
+    );
+
+    message.push_str(&format!("{}: {}\n", HOST_TRAILER, host));
+    message.push_str(&format!("{}: {}\n", SOURCE_BRANCH_TRAILER, source_branch));
+    message.push_str(&format!("{}: {}\n", BASE_TRAILER, base));
+    message.push_str(&format!(
+        "{}: {}\n",
+        ENCRYPTED_TRAILER,
+        if obfuscate_paths {
+            "contents, paths"
+        } else {
+            "contents"
+        }
+    ));
+    message.push_str(&format!("{}: {}\n", CIPHER_TRAILER, cipher));
+
+    message
+}
+
+pub fn get_trailer<'a>(message: &'a str, key: &str) -> Option<&'a str> {
+    message.lines().rev().find_map(|line| {
+        line.strip_prefix(key)?
+            .strip_prefix(':')
+            .map(|value| value.trim())
+    })
+}
+
+pub fn is_encrypted(commit: &Commit) -> bool {
+    get_trailer(commit.message().unwrap_or_default(), ENCRYPTED_TRAILER).is_some()
+}
+
+//One line per file, e.g. "M src/main.rs | +12 -3"
+fn get_changed_files(
+    repo: &Repository,
+    old_tree: &Tree,
+    new_tree: &Tree,
+) -> Result<Vec<String>, git2::Error> {
+    let diff = repo.diff_tree_to_tree(Some(old_tree), Some(new_tree), None)?;
+
+    let mut changes = Vec::new();
+    for (i, delta) in diff.deltas().enumerate() {
+        let status = match delta.status() {
+            Delta::Added => "A",
+            Delta::Deleted => "D",
+            Delta::Renamed => "R",
+            Delta::Typechange => "T",
+            _ => "M",
+        };
+
+        let path = delta
+            .new_file()
+            .path()
+            .or(delta.old_file().path())
+            .map(|path| path.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let stats = match Patch::from_diff(&diff, i)? {
+            Some(patch) if !patch.delta().flags().is_binary() => {
+                let (_, additions, deletions) = patch.line_stats()?;
+                format!("+{} -{}", additions, deletions)
+            }
+            _ => "binary".to_string(),
+        };
+
+        changes.push(format!("{} {} | {}", status, path, stats));
+    }
+
+    Ok(changes)
+}
@@ -0,0 +1,203 @@
+use chrono::{Duration, Local, NaiveDateTime};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    backup_executor,
+    data_structures::{
+        backup_ref::BackupRef,
+        config::{Config, RetentionPolicy},
+    },
+    snapshot_repo::SnapshotRepo,
+    utilities::git2_api_wrapper,
+};
+
+//Delete the backups of a repository that are no longer covered by the retention policy
+//Only the backups of this host are considered unless all_hosts is set
+//Returns the names of the deleted references, or the ones that would be deleted in a dry run
+pub fn prune(
+    repo_path: &str,
+    config: &Config,
+    dry_run: bool,
+    all_hosts: bool,
+) -> Result<Vec<String>, String> {
+    let retention = match config.retention {
+        Some(retention) => retention,
+        None => return Err("No retention policy is set, nothing will be pruned".to_string()),
+    };
+
+    let snapshot_repo = match SnapshotRepo::with_config(repo_path, config.clone()) {
+        Ok(repo) => repo,
+        Err(e) => return Err(format!("Failed to open {}: {}", repo_path, e)),
+    };
+
+    let host = backup_executor::get_host_name();
+    let backups: Vec<BackupRef> = match snapshot_repo.list_backups(None) {
+        Ok(backups) => backups
+            .into_iter()
+            .filter(|backup| all_hosts || backup.host == host)
+            .collect(),
+        Err(e) => return Err(format!("Failed to list the backups: {}", e)),
+    };
+
+    let expired: Vec<String> =
+        select_expired_backups(&backups, &retention, Local::now().naive_local())
+            .iter()
+            .map(|backup| backup.ref_name.clone())
+            .collect();
+
+    if expired.is_empty() || dry_run {
+        return Ok(expired);
+    }
+
+    let ref_names: Vec<&str> = expired.iter().map(|ref_name| ref_name.as_str()).collect();
+    match snapshot_repo.remote().and_then(|remote| {
+        git2_api_wrapper::delete_remote_refs(&snapshot_repo.repo, &ref_names, remote)
+    }) {
+        Ok(_) => Ok(expired),
+        Err(e) => Err(format!("Failed to delete the expired backups: {}", e)),
+    }
+}
+
+//Rolling backups have no time in their names and are never expired
+//Each one is a single reference, pruning its history would mean rewriting the commits pushed on top of each other
+fn select_expired_backups<'a>(
+    backups: &'a [BackupRef],
+    retention: &RetentionPolicy,
+    now: NaiveDateTime,
+) -> Vec<&'a BackupRef> {
+    let mut groups: HashMap<(&str, &str), Vec<(&BackupRef, NaiveDateTime)>> = HashMap::new();
+    for backup in backups {
+        if let Some(created_at) = backup.created_at {
+            groups
+                .entry((&backup.host, &backup.source_branch))
+                .or_default()
+                .push((backup, created_at));
+        }
+    }
+
+    let daily_cutoff = now - Duration::days(retention.keep_daily_days as i64);
+    let mut expired = Vec::new();
+
+    for group in groups.values_mut() {
+        //Latest first
+        group.sort_by_key(|(_, created_at)| Reverse(*created_at));
+
+        let mut kept_days = HashSet::new();
+        for (i, (backup, created_at)) in group.iter().enumerate() {
+            if i < retention.keep_last {
+                kept_days.insert(created_at.date());
+                continue;
+            }
+
+            if *created_at >= daily_cutoff && kept_days.insert(created_at.date()) {
+                continue;
+            }
+
+            expired.push(*backup);
+        }
+    }
+
+    expired
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use git2::Oid;
+
+    fn time(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    fn backup(name: &str, host: &str, created_at: Option<NaiveDateTime>) -> BackupRef {
+        BackupRef {
+            ref_name: name.to_string(),
+            host: host.to_string(),
+            source_branch: "main".to_string(),
+            created_at,
+            commit_id: Oid::zero(),
+        }
+    }
+
+    fn expired_names(backups: &[BackupRef], retention: &RetentionPolicy) -> Vec<String> {
+        let mut names: Vec<String> = select_expired_backups(backups, retention, time(10, 12))
+            .iter()
+            .map(|backup| backup.ref_name.clone())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn keeps_the_latest_backups() {
+        let backups = vec![
+            backup("a", "host", Some(time(1, 1))),
+            backup("b", "host", Some(time(2, 1))),
+            backup("c", "host", Some(time(3, 1))),
+            backup("d", "host", Some(time(4, 1))),
+        ];
+        let retention = RetentionPolicy {
+            keep_last: 2,
+            keep_daily_days: 0,
+        };
+
+        assert_eq!(expired_names(&backups, &retention), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn keeps_the_latest_backup_of_each_recent_day() {
+        let backups = vec![
+            backup("today-late", "host", Some(time(10, 11))),
+            backup("today-early", "host", Some(time(10, 9))),
+            backup("yesterday-late", "host", Some(time(9, 20))),
+            backup("yesterday-early", "host", Some(time(9, 8))),
+            backup("last-week", "host", Some(time(3, 8))),
+        ];
+        let retention = RetentionPolicy {
+            keep_last: 1,
+            keep_daily_days: 3,
+        };
+
+        assert_eq!(
+            expired_names(&backups, &retention),
+            vec!["last-week", "today-early", "yesterday-early"]
+        );
+    }
+
+    #[test]
+    fn hosts_are_pruned_separately_and_rolling_backups_are_kept() {
+        let backups = vec![
+            backup("laptop-old", "laptop", Some(time(1, 1))),
+            backup("laptop-new", "laptop", Some(time(2, 1))),
+            backup("desktop-old", "desktop", Some(time(1, 1))),
+            backup("rolling", "desktop", None),
+        ];
+        let retention = RetentionPolicy {
+            keep_last: 1,
+            keep_daily_days: 0,
+        };
+
+        assert_eq!(expired_names(&backups, &retention), vec!["laptop-old"]);
+    }
+
+    #[test]
+    fn rolling_backups_are_exempt_from_every_rule() {
+        let backups = vec![
+            backup("rolling", "host", None),
+            backup("old", "host", Some(time(1, 1))),
+            backup("new", "host", Some(time(10, 1))),
+        ];
+        let retention = RetentionPolicy {
+            keep_last: 0,
+            keep_daily_days: 0,
+        };
+
+        assert_eq!(expired_names(&backups, &retention), vec!["new", "old"]);
+        assert!(expired_names(&backups[..1], &retention).is_empty());
+    }
+}
@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::{
+    backup_encryptor, backup_executor, backup_message,
+    data_structures::backup_ref::BackupRef,
+    snapshot_repo::SnapshotRepo,
+    tool_initialiser,
+    utilities::{commit_signer, file_system, git2_api_wrapper},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestoreMode {
+    //Apply the backup as uncommitted changes in the working tree
+    Worktree,
+    //Create a new local branch pointing to the backup
+    Branch,
+    //Store the backup as a stash entry
+    Stash,
+}
+
+impl FromStr for RestoreMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "worktree" => Ok(RestoreMode::Worktree),
+            "branch" => Ok(RestoreMode::Branch),
+            "stash" => Ok(RestoreMode::Stash),
+            _ => Err(format!(
+                "Unknown restore mode {}, expected worktree, branch or stash",
+                s
+            )),
+        }
+    }
+}
+
+//Local backups are the snapshots stored in the repository itself, they are available offline
+pub fn restore(
+    folder: &str,
+    choice: Option<usize>,
+    mode: RestoreMode,
+    local: bool,
+    verify: bool,
+) -> Result<(), String> {
+    let snapshot_repo = match SnapshotRepo::new(folder) {
+        Ok(repo) => repo,
+        Err(e) => return Err(format!("Failed to open {}: {}", folder, e)),
+    };
+    let repo = &snapshot_repo.repo;
+
+    let (backup, current_branch) = choose_backup(&snapshot_repo, choice, local)?;
+
+    if verify {
+        let status = commit_signer::verify_commit(repo, backup.commit_id);
+        if !status.is_good() {
+            return Err(format!(
+                "Refusing to restore {}: {}",
+                backup.ref_name, status
+            ));
+        }
+        println!("{} has a {}", backup.ref_name, status);
+    }
+
+    if is_encrypted(&snapshot_repo, &backup) {
+        return Err(format!(
+            "{} is encrypted, use the decrypt command to get the files back",
+            backup.ref_name
+        ));
+    }
+
+    println!("Restoring {} as {:?}", backup.ref_name, mode);
+
+    let result = match mode {
+        RestoreMode::Worktree => restore_to_worktree(&snapshot_repo, &backup),
+        RestoreMode::Branch => restore_to_branch(&snapshot_repo, &backup),
+        RestoreMode::Stash => restore_to_stash(&snapshot_repo, &backup, &current_branch),
+    };
+
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("Failed to restore {}: {}", backup.ref_name, e)),
+    }
+}
+
+//Write the files of an encrypted backup into a new folder, the repository itself is left untouched
+pub fn decrypt(folder: &str, choice: Option<usize>, output: &str) -> Result<(), String> {
+    if file_system::is_path_exist(output) && !file_system::is_empty_folder(output) {
+        return Err(format!("{} is not an empty folder", output));
+    }
+
+    let snapshot_repo = match SnapshotRepo::new(folder) {
+        Ok(repo) => repo,
+        Err(e) => return Err(format!("Failed to open {}: {}", folder, e)),
+    };
+    let repo = &snapshot_repo.repo;
+
+    let (backup, _) = choose_backup(&snapshot_repo, choice, false)?;
+
+    println!("Decrypting {} into {}", backup.ref_name, output);
+
+    let result = repo
+        .find_commit(backup.commit_id)
+        .and_then(|commit| backup_encryptor::decrypt_backup(repo, &commit))
+        .and_then(|tree_id| {
+            git2_api_wrapper::checkout_tree_to_dir(repo, tree_id, Path::new(output))
+        });
+
+    match result {
+        Ok(_) => {
+            println!("The files of {} are written to {}", backup.ref_name, output);
+            Ok(())
+        }
+        Err(e) => Err(format!("Failed to decrypt {}: {}", backup.ref_name, e)),
+    }
+}
+
+//Lists the backups of the current branch, asks for one if no choice is given and fetches it
+//Returns the backup along with the current branch
+fn choose_backup(
+    snapshot_repo: &SnapshotRepo,
+    choice: Option<usize>,
+    local: bool,
+) -> Result<(BackupRef, String), String> {
+    let repo = &snapshot_repo.repo;
+
+    let current_branch = match git2_api_wrapper::get_current_branch_name(repo) {
+        Ok(branch) => branch,
+        Err(e) => return Err(format!("Failed to get the current branch: {}", e)),
+    };
+
+    let backups = if local {
+        snapshot_repo.list_local_backups().map(|backups| {
+            backups
+                .into_iter()
+                .filter(|backup| backup.source_branch == current_branch)
+                .collect()
+        })
+    } else {
+        snapshot_repo.list_backups(Some(&current_branch))
+    };
+    let backups: Vec<BackupRef> = match backups {
+        Ok(backups) => backups,
+        Err(e) => return Err(format!("Failed to list the backups: {}", e)),
+    };
+
+    if backups.is_empty() {
+        return Err(format!("No backup is found for branch {}", current_branch));
+    }
+
+    let backup = match choice {
+        Some(choice) => match backups.get(choice) {
+            Some(backup) => backup,
+            None => return Err(format!("There is no backup number {}", choice)),
+        },
+        None => select_backup(&backups, &current_branch)?,
+    };
+
+    if repo.find_commit(backup.commit_id).is_err() {
+        if let Err(e) = snapshot_repo.remote().and_then(|remote| {
+            git2_api_wrapper::fetch_remote_refs(repo, &[&backup.ref_name], remote)
+        }) {
+            return Err(format!("Failed to fetch {}: {}", backup.ref_name, e));
+        }
+    }
+
+    Ok((backup.clone(), current_branch))
+}
+
+fn is_encrypted(snapshot_repo: &SnapshotRepo, backup: &BackupRef) -> bool {
+    match snapshot_repo.repo.find_commit(backup.commit_id) {
+        Ok(commit) => backup_message::is_encrypted(&commit),
+        Err(_) => false,
+    }
+}
+
+fn select_backup<'a>(
+    backups: &'a [BackupRef],
+    current_branch: &str,
+) -> Result<&'a BackupRef, String> {
+    println!("Backups of branch {}:", current_branch);
+    for (i, backup) in backups.iter().enumerate() {
+        println!("[{}] {} (host: {})", i, backup.ref_name, backup.host);
+    }
+
+    let input =
+        tool_initialiser::user_input_handler("Please enter the number of the backup to restore:");
+    match input.trim().parse::<usize>() {
+        Ok(choice) if choice < backups.len() => Ok(&backups[choice]),
+        _ => Err(format!("{} is not a valid choice", input.trim())),
+    }
+}
+
+fn restore_to_worktree(
+    snapshot_repo: &SnapshotRepo,
+    backup: &BackupRef,
+) -> Result<(), git2::Error> {
+    let repo = &snapshot_repo.repo;
+    let backup_commit = repo.find_commit(backup.commit_id)?;
+    let (base, index_commit) = git2_api_wrapper::get_backup_parts(&backup_commit)?;
+
+    //What was staged is staged again, the rest is left as unstaged changes
+    match index_commit {
+        Some(index_commit) => {
+            let base_tree = base.tree()?;
+            let index_tree = index_commit.tree()?;
+            let backup_tree = backup_commit.tree()?;
+
+            //Both halves are checked first, so a local edit does not leave only the staged half applied
+            //The unstaged half lands on top of the staged one, so the whole backup is checked against the worktree
+            git2_api_wrapper::check_apply_to_index_and_worktree(repo, &base_tree, &index_tree)?;
+            git2_api_wrapper::check_apply_to_worktree(repo, &base_tree, &backup_tree)?;
+
+            git2_api_wrapper::apply_to_index_and_worktree(repo, &base_tree, &index_tree)?;
+            git2_api_wrapper::apply_to_worktree(repo, &index_tree, &backup_tree)?;
+        }
+        None => {
+            git2_api_wrapper::apply_to_worktree(repo, &base.tree()?, &backup_commit.tree()?)?;
+        }
+    }
+    println!(
+        "The changes of {} are applied to {}",
+        backup.ref_name, snapshot_repo.path
+    );
+    Ok(())
+}
+
+fn restore_to_branch(snapshot_repo: &SnapshotRepo, backup: &BackupRef) -> Result<(), git2::Error> {
+    let repo = &snapshot_repo.repo;
+    let backup_commit = repo.find_commit(backup.commit_id)?;
+    //Several snapshots of the same host and branch can be restored side by side
+    let short_id = backup_commit.as_object().short_id()?;
+    let branch_name = format!(
+        "restore/{}/{}_{}",
+        backup.host,
+        backup.source_branch,
+        short_id.as_str().unwrap_or_default()
+    );
+
+    git2_api_wrapper::create_local_branch(repo, &branch_name, &backup_commit)
+}
+
+fn restore_to_stash(
+    snapshot_repo: &SnapshotRepo,
+    backup: &BackupRef,
+    current_branch: &str,
+) -> Result<(), git2::Error> {
+    let repo = &snapshot_repo.repo;
+    let backup_commit = repo.find_commit(backup.commit_id)?;
+    let (base, index_commit) = git2_api_wrapper::get_backup_parts(&backup_commit)?;
+    let index_tree = match index_commit {
+        Some(index_commit) => index_commit.tree()?,
+        None => base.tree()?,
+    };
+
+    git2_api_wrapper::store_as_stash(
+        repo,
+        &base,
+        &index_tree,
+        &backup_commit.tree()?,
+        current_branch,
+        &format!("CommitPal restore of {}", backup.ref_name),
+    )?;
+    println!("{} is stored as stash@{{0}}", backup.ref_name);
+    Ok(())
+}
+
+//Pick up the latest backup another machine made of the current branch
+//The backup is three-way merged into the working tree, nothing is written if there is any conflict
+pub fn resume(folder: &str, verify: bool) -> Result<(), String> {
+    let snapshot_repo = match SnapshotRepo::new(folder) {
+        Ok(repo) => repo,
+        Err(e) => return Err(format!("Failed to open {}: {}", folder, e)),
+    };
+    let repo = &snapshot_repo.repo;
+
+    let current_branch = match git2_api_wrapper::get_current_branch_name(repo) {
+        Ok(branch) => branch,
+        Err(e) => return Err(format!("Failed to get the current branch: {}", e)),
+    };
+
+    let host = backup_executor::get_host_name();
+    let backups: Vec<BackupRef> = match snapshot_repo.list_backups(Some(&current_branch)) {
+        Ok(backups) => backups
+            .into_iter()
+            .filter(|backup| backup.host != host)
+            .collect(),
+        Err(e) => return Err(format!("Failed to list the backups: {}", e)),
+    };
+
+    if backups.is_empty() {
+        return Err(format!(
+            "No backup of branch {} is found from other machines",
+            current_branch
+        ));
+    }
+
+    //Only the rolling backup and the latest timestamped backup of each host are fetched
+    //Hosts can be in different time zones and rolling backups have no time in their names, so their commit times tell the latest one
+    let mut candidates: HashMap<(&str, bool), &BackupRef> = HashMap::new();
+    for backup in &backups {
+        let candidate = candidates
+            .entry((&backup.host, backup.created_at.is_some()))
+            .or_insert(backup);
+        if backup.created_at > candidate.created_at {
+            *candidate = backup;
+        }
+    }
+
+    let missing_ref_names: Vec<&str> = candidates
+        .values()
+        .filter(|backup| repo.find_commit(backup.commit_id).is_err())
+        .map(|backup| backup.ref_name.as_str())
+        .collect();
+    if !missing_ref_names.is_empty() {
+        if let Err(e) = snapshot_repo.remote().and_then(|remote| {
+            git2_api_wrapper::fetch_remote_refs(repo, &missing_ref_names, remote)
+        }) {
+            return Err(format!("Failed to fetch the backups: {}", e));
+        }
+    }
+
+    let latest = candidates
+        .values()
+        .filter_map(|backup| {
+            let commit = repo.find_commit(backup.commit_id).ok()?;
+            Some((commit.time().seconds(), *backup))
+        })
+        .max_by_key(|(time, _)| *time)
+        .map(|(_, backup)| backup);
+
+    let latest = match latest {
+        Some(latest) => latest,
+        None => return Err("Failed to read the fetched backups".to_string()),
+    };
+
+    if verify {
+        let status = commit_signer::verify_commit(repo, latest.commit_id);
+        if !status.is_good() {
+            return Err(format!(
+                "Refusing to resume from {}: {}",
+                latest.ref_name, status
+            ));
+        }
+        println!("{} has a {}", latest.ref_name, status);
+    }
+
+    if is_encrypted(&snapshot_repo, latest) {
+        return Err(format!(
+            "{} is encrypted, use the decrypt command to get the files back",
+            latest.ref_name
+        ));
+    }
+
+    println!("Resuming from {} (host: {})", latest.ref_name, latest.host);
+
+    match merge_into_worktree(&snapshot_repo, latest) {
+        Ok(conflicted_paths) if conflicted_paths.is_empty() => {
+            println!("{} is merged into {}", latest.ref_name, folder);
+            Ok(())
+        }
+        Ok(conflicted_paths) => {
+            let mut report = format!(
+                "{} conflicts with your local changes, nothing is changed.\nConflicting files:",
+                latest.ref_name
+            );
+            for path in conflicted_paths {
+                report.push_str(&format!("\n  {}", path));
+            }
+            report.push_str("\nUse the restore command with --mode branch or --mode stash to resolve them manually");
+            Err(report)
+        }
+        Err(e) => Err(format!("Failed to merge {}: {}", latest.ref_name, e)),
+    }
+}
+
+//Returns the conflicting paths, the working tree is only updated when there is none
+fn merge_into_worktree(
+    snapshot_repo: &SnapshotRepo,
+    backup: &BackupRef,
+) -> Result<Vec<String>, git2::Error> {
+    let repo = &snapshot_repo.repo;
+    let backup_commit = repo.find_commit(backup.commit_id)?;
+    let base_tree = git2_api_wrapper::get_backup_parts(&backup_commit)?
+        .0
+        .tree()?;
+    let their_tree = backup_commit.tree()?;
+    let our_tree = repo.find_tree(snapshot_repo.build_snapshot_tree()?)?;
+
+    let mut merged_index = repo.merge_trees(&base_tree, &our_tree, &their_tree, None)?;
+    let conflicted_paths = git2_api_wrapper::get_conflicted_paths(&merged_index)?;
+    if !conflicted_paths.is_empty() {
+        return Ok(conflicted_paths);
+    }
+
+    let merged_tree = repo.find_tree(merged_index.write_tree_to(repo)?)?;
+    git2_api_wrapper::apply_to_worktree(repo, &our_tree, &merged_tree)?;
+    Ok(conflicted_paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_structures::config::Config;
+    use git2::{Oid, Repository, Signature};
+    use std::fs;
+
+    fn write_tree(repo: &Repository, files: &[(&str, &str)]) -> Oid {
+        let mut builder = repo.treebuilder(None).unwrap();
+        for (name, content) in files {
+            let blob_id = repo.blob(content.as_bytes()).unwrap();
+            builder.insert(name, blob_id, 0o100644).unwrap();
+        }
+        builder.write().unwrap()
+    }
+
+    #[test]
+    fn a_failed_restore_leaves_the_index_and_worktree_untouched() {
+        let folder = tempfile::tempdir().unwrap();
+        let repo = Repository::init(folder.path()).unwrap();
+        let signature = Signature::now("CommitPal", "commitpal@example.com").unwrap();
+
+        //The base commit is checked out, b.txt then gets a local edit
+        let base_tree = repo
+            .find_tree(write_tree(&repo, &[("a.txt", "one\n"), ("b.txt", "one\n")]))
+            .unwrap();
+        let base_id = repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "base",
+                &base_tree,
+                &[],
+            )
+            .unwrap();
+        let base = repo.find_commit(base_id).unwrap();
+        repo.checkout_head(Some(git2::build::CheckoutBuilder::new().force()))
+            .unwrap();
+        fs::write(folder.path().join("b.txt"), "local\n").unwrap();
+
+        //a.txt was staged in the backup, b.txt only changed in the worktree
+        let index_tree = repo
+            .find_tree(write_tree(&repo, &[("a.txt", "two\n"), ("b.txt", "one\n")]))
+            .unwrap();
+        let index_id = repo
+            .commit(None, &signature, &signature, "index", &index_tree, &[&base])
+            .unwrap();
+        let backup_tree = repo
+            .find_tree(write_tree(&repo, &[("a.txt", "two\n"), ("b.txt", "two\n")]))
+            .unwrap();
+        let backup_id = repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "backup",
+                &backup_tree,
+                &[&base, &repo.find_commit(index_id).unwrap()],
+            )
+            .unwrap();
+
+        let snapshot_repo =
+            SnapshotRepo::with_config(&folder.path().to_string_lossy(), Config::new()).unwrap();
+        let backup = BackupRef {
+            ref_name: "refs/commitpal/host/main".to_string(),
+            host: "host".to_string(),
+            source_branch: "main".to_string(),
+            created_at: None,
+            commit_id: backup_id,
+        };
+
+        assert!(restore_to_worktree(&snapshot_repo, &backup).is_err());
+
+        assert_eq!(
+            fs::read_to_string(folder.path().join("a.txt")).unwrap(),
+            "one\n"
+        );
+        assert_eq!(
+            fs::read_to_string(folder.path().join("b.txt")).unwrap(),
+            "local\n"
+        );
+        let index = repo.index().unwrap();
+        let staged_a = index.get_path(Path::new("a.txt"), 0).unwrap();
+        assert_eq!(staged_a.id, base_tree.get_name("a.txt").unwrap().id());
+    }
+}
@@ -1,37 +1,57 @@
-use crate::cross_platform_constant;
-use crate::data_structures::config::Config;
+use crate::data_structures::config::{
+    BackupBranchMode, CommitIdentity, Config, RepositorySettings, RetentionPolicy, SecretRule,
+    SecretScanPolicy, SnapshotPolicy, BRANCH_NAME_BRANCH_PLACEHOLDER, BRANCH_NAME_HOST_PLACEHOLDER,
+    CONFIG_VERSION,
+};
 use crate::utilities::file_system::{
-    create_file_recursively, is_git_repository, is_path_exist, read_file_to_string,
-    write_string_to_file,
+    copy_file, create_file_recursively, get_absolute_path, is_git_repository, is_path_exist,
+    read_file_to_string, rename_file, write_string_to_file,
 };
+use crate::utilities::git2_api_wrapper;
+use crate::{config_migrator, config_schema, cross_platform_constant, tool_initialiser};
+use fs2::FileExt;
+use regex::Regex;
+use serde_json::Value;
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::process;
+
+#[cfg(target_os = "windows")]
+const DEFAULT_EDITOR: &str = "notepad";
+#[cfg(not(target_os = "windows"))]
+const DEFAULT_EDITOR: &str = "vi";
 
 pub fn reset() {
+    let _lock = lock_config();
     let config = Config::new();
-    match write_config(config) {
+    match write_config(&config) {
         Ok(_) => println!("Created a new config file successfully!"),
         Err(e) => panic!("Failed to reset the config: {}", e),
     }
 }
 
 pub fn remove_watched_folder(folder: &str) {
+    let _lock = lock_config();
     let mut config = read_config();
     config.remove_watching_folder(folder);
-    match write_config(config) {
+    match write_config(&config) {
         Ok(_) => println!("config is updated successfully!"),
         Err(e) => panic!("Failed to remove {}: {}", folder, e),
     }
 }
 
 pub fn clean_watched_folder() {
+    let _lock = lock_config();
     let mut config = read_config();
     config.clean_watching_folders();
-    match write_config(config) {
+    match write_config(&config) {
         Ok(_) => println!("config is updated successfully!"),
         Err(e) => panic!("Failed to clean the watch list: {}", e),
     }
 }
 
 pub fn add_watched_folder(folder: &str) {
+    let folder = &get_absolute_path(folder);
     if !is_path_exist(folder) {
         println!("{} does not exist, it will be ignored", folder);
         return;
@@ -42,9 +62,10 @@ pub fn add_watched_folder(folder: &str) {
         return;
     }
 
+    let _lock = lock_config();
     let mut config = read_config();
     config.insert_watching_folder(folder.to_string());
-    match write_config(config) {
+    match write_config(&config) {
         Ok(_) => println!("{} is added to the watch list", folder),
         Err(e) => panic!("Failed to store {}: {}", folder, e),
     }
@@ -58,33 +79,752 @@ pub fn list_watched_folder() {
     }
 
     println!("Watching {} Folders:", config.watching_folders.len());
-    for folder in config.watching_folders {
-        println!("{}", folder);
+    for (folder, settings) in &config.watching_folders {
+        let mut overrides = Vec::new();
+        if settings.paused {
+            overrides.push("paused".to_string());
+        }
+        if let Some(frequency) = settings.backup_frequency {
+            overrides.push(format!("every {} minutes", frequency));
+        }
+        if let Some(buffer_time) = settings.change_detection_buffer {
+            overrides.push(format!("buffer {} minutes", buffer_time));
+        }
+        if let Some(remote) = &settings.backup_remote {
+            overrides.push(format!("remote {}", remote));
+        }
+        if let Some(url) = &settings.backup_url {
+            overrides.push(format!("url {}", url));
+        }
+        if let Some(template) = &settings.branch_name_template {
+            overrides.push(format!("branch name {}", template));
+        }
+        if let Some(policy) = settings.snapshot_policy {
+            overrides.push(format!("policy {:?}", policy));
+        }
+        if let Some(identity) = &settings.commit_identity {
+            overrides.push(format!("identity {} <{}>", identity.name, identity.email));
+        }
+
+        if overrides.is_empty() {
+            println!("{}", folder);
+        } else {
+            println!("{} ({})", folder, overrides.join(", "));
+        }
     }
 }
 
-pub fn set_backup_frequency(frequency: u64) {
+//Changes the settings of a watched folder, the folder has to be added first
+fn update_repository_settings<F>(folder: &str, setting_name: &str, update: F)
+where
+    F: FnOnce(&mut RepositorySettings),
+{
+    let _lock = lock_config();
+    let mut config = read_config();
+    match config.watching_folders.get_mut(folder) {
+        Some(settings) => update(settings),
+        None => {
+            println!("{} is not being watched, please add it first", folder);
+            return;
+        }
+    }
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set the {} of {}: {}", setting_name, folder, e),
+    }
+}
+
+//With a folder, None removes the override of that folder
+pub fn set_backup_frequency(folder: Option<&str>, frequency: Option<u64>) {
+    if let Some(frequency) = frequency {
+        if let Err(e) = check_minutes("backup_frequency", frequency) {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    let frequency = match (folder, frequency) {
+        (Some(folder), frequency) => {
+            update_repository_settings(folder, "backup frequency", |settings| {
+                settings.backup_frequency = frequency
+            });
+            return;
+        }
+        (None, Some(frequency)) => frequency,
+        (None, None) => {
+            println!("Please provide the backup frequency");
+            return;
+        }
+    };
+
+    let _lock = lock_config();
     let mut config = read_config();
     config.backup_frequency = frequency;
-    match write_config(config) {
+    match write_config(&config) {
         Ok(_) => println!("config is updated successfully!"),
         Err(e) => panic!("Failed to set backup frequency: {}", e),
     }
 }
 
-pub fn set_change_buffer_time(buffer_time: u64) {
+//With a folder, None removes the override of that folder
+pub fn set_change_buffer_time(folder: Option<&str>, buffer_time: Option<u64>) {
+    if let Some(buffer_time) = buffer_time {
+        if let Err(e) = check_minutes("change_detection_buffer", buffer_time) {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    let buffer_time = match (folder, buffer_time) {
+        (Some(folder), buffer_time) => {
+            update_repository_settings(folder, "change buffer time", |settings| {
+                settings.change_detection_buffer = buffer_time
+            });
+            return;
+        }
+        (None, Some(buffer_time)) => buffer_time,
+        (None, None) => {
+            println!("Please provide the buffer time");
+            return;
+        }
+    };
+
+    let _lock = lock_config();
     let mut config = read_config();
     config.change_detection_buffer = buffer_time;
-    match write_config(config) {
+    match write_config(&config) {
         Ok(_) => println!("config is updated successfully!"),
         Err(e) => panic!("Failed to set change buffer time: {}", e),
     }
 }
 
+//A paused folder stays in the watch list with its settings, but it is not backed up
+pub fn set_paused(folder: &str, paused: bool) {
+    update_repository_settings(folder, "paused state", |settings| settings.paused = paused);
+}
+
+//The template has to contain {host} and {branch} once with a / between them, e.g. {branch}/{host}
+//With a folder, None removes the override of that folder
+pub fn set_branch_name_template(folder: Option<&str>, template: Option<&str>) {
+    if let Some(template) = template {
+        if let Err(e) = check_branch_name_template(template) {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    let template = match (folder, template) {
+        (Some(folder), template) => {
+            update_repository_settings(folder, "branch name template", |settings| {
+                settings.branch_name_template = template.map(|template| template.to_string())
+            });
+            return;
+        }
+        (None, Some(template)) => template,
+        (None, None) => {
+            println!("Please provide the branch name template");
+            return;
+        }
+    };
+
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.branch_name_template = template.to_string();
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set the branch name template: {}", e),
+    }
+}
+
+pub fn set_backup_branch_mode(mode: BackupBranchMode) {
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.backup_branch_mode = mode;
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set backup branch mode: {}", e),
+    }
+}
+
+pub fn set_backup_ref_namespace(namespace: &str) {
+    let namespace = namespace.trim_end_matches('/');
+    if let Err(e) = check_backup_ref_namespace(namespace) {
+        println!("{}", e);
+        return;
+    }
+
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.backup_ref_namespace = namespace.to_string();
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set backup reference namespace: {}", e),
+    }
+}
+
+pub fn set_retention_policy(retention: Option<RetentionPolicy>) {
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.retention = retention;
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set retention policy: {}", e),
+    }
+}
+
+pub fn set_backup_remote(folder: &str, remote_name: Option<&str>) {
+    if let Some(remote_name) = remote_name {
+        let remote_exists = git2::Repository::open(folder)
+            .map(|repo| repo.find_remote(remote_name).is_ok())
+            .unwrap_or(false);
+        if !remote_exists {
+            println!("{} has no remote named {}", folder, remote_name);
+            return;
+        }
+    }
+
+    update_repository_settings(folder, "backup remote", |settings| {
+        settings.backup_remote = remote_name.map(|remote_name| remote_name.to_string())
+    });
+}
+
+//Without a folder, the url is used by every repository that has no backup url or remote of its own
+pub fn set_backup_url(folder: Option<&str>, url: Option<&str>) {
+    if let Some(url) = url {
+        if let Err(e) = check_backup_url(url) {
+            println!("{}", e);
+            return;
+        }
+    }
+
+    if let Some(folder) = folder {
+        update_repository_settings(folder, "backup url", |settings| {
+            settings.backup_url = url.map(|url| url.to_string())
+        });
+        return;
+    }
+
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.backup_url = url.map(|url| url.to_string());
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set the backup url: {}", e),
+    }
+}
+
+pub fn set_encryption(encrypt_backups: bool, obfuscate_paths: bool) {
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.encrypt_backups = encrypt_backups;
+    config.obfuscate_paths = encrypt_backups && obfuscate_paths;
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set the encryption: {}", e),
+    }
+}
+
+pub fn set_local_only(local_only: bool) {
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.local_only = local_only;
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set local only mode: {}", e),
+    }
+}
+
+//Without a folder, the policy is used by every repository that has no policy of its own
+pub fn set_snapshot_policy(folder: Option<&str>, policy: SnapshotPolicy) {
+    if let Some(folder) = folder {
+        update_repository_settings(folder, "snapshot policy", |settings| {
+            settings.snapshot_policy = Some(policy)
+        });
+        return;
+    }
+
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.snapshot_policy = policy;
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set the snapshot policy: {}", e),
+    }
+}
+
+//With a folder, the identity overrides the git config of that repository, None removes the override
+//Without a folder, the identity is the fallback for repositories without user.name and user.email
+pub fn set_commit_identity(folder: Option<&str>, identity: Option<CommitIdentity>) {
+    let identity = match (folder, identity) {
+        (Some(folder), identity) => {
+            update_repository_settings(folder, "commit identity", |settings| {
+                settings.commit_identity = identity
+            });
+            return;
+        }
+        (None, Some(identity)) => identity,
+        (None, None) => {
+            println!("Please provide both the name and the email of the fallback identity");
+            return;
+        }
+    };
+
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.commit_identity = identity;
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set the commit identity: {}", e),
+    }
+}
+
+pub fn set_secret_scan_policy(policy: SecretScanPolicy) {
+    let _lock = lock_config();
+    let mut config = read_config();
+    config.secret_scan_policy = policy;
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to set the secret scan policy: {}", e),
+    }
+}
+
+//A rule with the same name is replaced
+pub fn add_secret_rule(rule: SecretRule) {
+    if let Err(e) = check_secret_rule(&rule) {
+        println!("{}", e);
+        return;
+    }
+
+    let _lock = lock_config();
+    let mut config = read_config();
+    config
+        .secret_rules
+        .retain(|secret_rule| secret_rule.name != rule.name);
+    config.secret_rules.push(rule);
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to add the secret rule: {}", e),
+    }
+}
+
+pub fn remove_secret_rule(name: &str) {
+    let _lock = lock_config();
+    let mut config = read_config();
+    let count = config.secret_rules.len();
+    config
+        .secret_rules
+        .retain(|secret_rule| secret_rule.name != name);
+    if config.secret_rules.len() == count {
+        println!("There is no secret rule named {}", name);
+        return;
+    }
+    match write_config(&config) {
+        Ok(_) => println!("config is updated successfully!"),
+        Err(e) => panic!("Failed to remove the secret rule: {}", e),
+    }
+}
+
+//Without a key, the whole config is printed as it is written to the config file
+pub fn get_config_value(key: Option<&str>) {
+    let config = read_config();
+    let key = match key {
+        Some(key) => key,
+        None => {
+            match config_schema::to_commented_toml(&config) {
+                Ok(config_toml) => print!("{}", config_toml),
+                Err(e) => println!("{}", e),
+            }
+            return;
+        }
+    };
+
+    let path = match config_schema::parse_key(key) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let table = to_toml_table(&config);
+    let mut value = None;
+    for (i, name) in path.iter().enumerate() {
+        let parent = if i == 0 {
+            Some(&table)
+        } else {
+            value.and_then(toml::Value::as_table)
+        };
+        value = parent.and_then(|parent| parent.get(name));
+    }
+
+    match value {
+        None => println!("{} is not set", key),
+        Some(toml::Value::String(value)) => println!("{}", value),
+        Some(toml::Value::Table(value)) => print!("{}", value),
+        Some(value) => println!("{}", value),
+    }
+}
+
+//The value is read as TOML, e.g. 10, true or { keep_last = 3, keep_daily_days = 7 }, anything else is a string
+pub fn set_config_value(key: &str, value: &str) {
+    let path = match config_schema::parse_key(key) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let value = match toml::from_str::<toml::Table>(&format!("value = {}", value)) {
+        Ok(mut table) => match table.remove("value") {
+            Some(value) => value,
+            None => toml::Value::String(value.to_string()),
+        },
+        Err(_) => toml::Value::String(value.to_string()),
+    };
+
+    //Folders are added by the add command, which checks they are git repositories
+    if path == ["watching_folders"] {
+        println!("Use the add and remove commands to change the watched folders");
+        return;
+    }
+
+    let _lock = lock_config();
+    let config = read_config();
+    if path.len() >= 2
+        && path[0] == "watching_folders"
+        && !config.watching_folders.contains_key(&path[1])
+    {
+        println!("{} is not being watched, please add it first", path[1]);
+        return;
+    }
+
+    let mut table = to_toml_table(&config);
+    let mut parent = &mut table;
+    for name in &path[..path.len() - 1] {
+        parent = match parent
+            .entry(name.clone())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+        {
+            toml::Value::Table(table) => table,
+            _ => {
+                println!("{} is not a table", name);
+                return;
+            }
+        };
+    }
+    parent.insert(path[path.len() - 1].clone(), value);
+
+    match from_toml_table(table) {
+        Ok(config) => match write_config(&config) {
+            Ok(_) => println!("config is updated successfully!"),
+            Err(e) => panic!("Failed to set {}: {}", key, e),
+        },
+        Err(e) => println!("Invalid value for {}: {}", key, e),
+    }
+}
+
+//A top level key goes back to its default, a nested one is removed
+//Only the overrides of a watched folder can be unset, the remove command stops watching it
+pub fn unset_config_value(key: &str) {
+    let path = match config_schema::parse_key(key) {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if path[0] == "watching_folders" && path.len() <= 2 {
+        println!("Use the remove or clean command to stop watching folders");
+        return;
+    }
+
+    let _lock = lock_config();
+    let mut table = to_toml_table(&read_config());
+    let name = &path[path.len() - 1];
+    if path.len() == 1 {
+        match to_toml_table(&Config::new()).remove(name) {
+            Some(default) => table.insert(name.clone(), default),
+            None => table.remove(name),
+        };
+    } else {
+        let mut parent = Some(&mut table);
+        for name in &path[..path.len() - 1] {
+            parent = parent
+                .and_then(|parent| parent.get_mut(name))
+                .and_then(toml::Value::as_table_mut);
+        }
+        if parent.and_then(|parent| parent.remove(name)).is_none() {
+            println!("{} is not set", key);
+            return;
+        }
+    }
+
+    match from_toml_table(table) {
+        Ok(config) => match write_config(&config) {
+            Ok(_) => println!("config is updated successfully!"),
+            Err(e) => panic!("Failed to unset {}: {}", key, e),
+        },
+        Err(e) => println!("Cannot unset {}: {}", key, e),
+    }
+}
+
+//The config is edited in a copy, which only replaces the config file once it is valid
+//The lock is held while the editor is open, so the edit does not overwrite changes made meanwhile
+pub fn edit_config() {
+    let _lock = lock_config();
+    let config = read_config();
+    let config_path = match cross_platform_constant::get_config_path() {
+        Ok(config_path) => config_path,
+        Err(e) => panic!("Failed to get config path: {}", e),
+    };
+    let original = if is_path_exist(&config_path) {
+        read_file_to_string(&config_path)
+    } else {
+        config_schema::to_commented_toml(&config)
+    };
+    let original = match original {
+        Ok(original) => original,
+        Err(e) => panic!("Failed to read the config file : {}", e),
+    };
+
+    let edited_file = match tempfile::Builder::new()
+        .prefix("commitpal-config-")
+        .suffix(".toml")
+        .tempfile()
+    {
+        Ok(edited_file) => edited_file,
+        Err(e) => panic!("Failed to create a temporary file: {}", e),
+    };
+    let edited_path = edited_file.path().to_string_lossy().to_string();
+    if let Err(e) = write_string_to_file(&edited_path, original.clone()) {
+        panic!("Failed to copy the config file: {}", e);
+    }
+
+    loop {
+        if let Err(e) = open_editor(&edited_path) {
+            println!("{}", e);
+            return;
+        }
+
+        let edited = match read_file_to_string(&edited_path) {
+            Ok(edited) => edited,
+            Err(e) => panic!("Failed to read the edited config: {}", e),
+        };
+        if edited == original {
+            println!("The config is not changed");
+            return;
+        }
+
+        //A file without the version would be migrated again from version 0 on every read
+        let result = parse_config(&edited, false)
+            .map_err(|e| format!("The edited config {}", e))
+            .and_then(|(config, version)| match version {
+                CONFIG_VERSION => validate_config(&config),
+                _ => Err(format!(
+                    "The edited config has to keep version = {}",
+                    CONFIG_VERSION
+                )),
+            });
+        match result {
+            Ok(_) => {
+                match write_config_file(edited) {
+                    Ok(_) => println!("config is updated successfully!"),
+                    Err(e) => panic!("Failed to write the config file: {}", e),
+                }
+                return;
+            }
+            Err(e) => println!("{}", e),
+        }
+
+        let answer = tool_initialiser::user_input_handler("Edit again? [Y/n]");
+        if answer.trim().eq_ignore_ascii_case("n") {
+            println!("The changes are discarded");
+            return;
+        }
+    }
+}
+
+//$VISUAL, then $EDITOR, may contain arguments, e.g. code --wait
+fn open_editor(path: &str) -> Result<(), String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| DEFAULT_EDITOR.to_string());
+    let mut args = editor.split_whitespace();
+    let program = match args.next() {
+        Some(program) => program,
+        None => return Err("The editor is empty, please set $EDITOR".to_string()),
+    };
+
+    match process::Command::new(program).args(args).arg(path).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!(
+            "{} exited with {}, the config is not changed",
+            editor, status
+        )),
+        Err(e) => Err(format!(
+            "Failed to open {}: {}, please set $EDITOR",
+            editor, e
+        )),
+    }
+}
+
+fn to_toml_table(config: &Config) -> toml::Table {
+    match toml::Table::try_from(config) {
+        Ok(table) => table,
+        Err(e) => panic!("Failed to serialise config to toml: {}", e),
+    }
+}
+
+//Unknown keys are already refused by parse_key, serde checks the types and validate_config the rest
+fn from_toml_table(table: toml::Table) -> Result<Config, String> {
+    let config: Config = match toml::Value::Table(table).try_into() {
+        Ok(config) => config,
+        Err(e) => return Err(e.message().to_string()),
+    };
+    validate_config(&config)?;
+    Ok(config)
+}
+
+fn check_branch_name_template(template: &str) -> Result<(), String> {
+    let is_valid = template.matches(BRANCH_NAME_HOST_PLACEHOLDER).count() == 1
+        && template.matches(BRANCH_NAME_BRANCH_PLACEHOLDER).count() == 1
+        && has_separator_between_placeholders(template)
+        && git2::Reference::is_valid_name(&format!(
+            "refs/backup/{}_2024-01-01_00-00-00",
+            template
+                .replace(BRANCH_NAME_HOST_PLACEHOLDER, "host")
+                .replace(BRANCH_NAME_BRANCH_PLACEHOLDER, "branch")
+        ));
+    if !is_valid {
+        return Err(format!(
+            "{} is not a valid branch name template, it should contain {} and {} once with a / between them, e.g. {}/{}",
+            template,
+            BRANCH_NAME_HOST_PLACEHOLDER,
+            BRANCH_NAME_BRANCH_PLACEHOLDER,
+            BRANCH_NAME_HOST_PLACEHOLDER,
+            BRANCH_NAME_BRANCH_PLACEHOLDER
+        ));
+    }
+    Ok(())
+}
+
+//A branch name can contain any character but /, so the host is only told apart from the branch by a /
+fn has_separator_between_placeholders(template: &str) -> bool {
+    let (host, branch) = match (
+        template.find(BRANCH_NAME_HOST_PLACEHOLDER),
+        template.find(BRANCH_NAME_BRANCH_PLACEHOLDER),
+    ) {
+        (Some(host), Some(branch)) => (host, branch),
+        _ => return false,
+    };
+
+    let between = if host < branch {
+        &template[host + BRANCH_NAME_HOST_PLACEHOLDER.len()..branch]
+    } else {
+        &template[branch + BRANCH_NAME_BRANCH_PLACEHOLDER.len()..host]
+    };
+    between.contains('/')
+}
+
+//A frequency or buffer of 0 minutes would make the executor check the repositories in a busy loop
+fn check_minutes(key: &str, minutes: u64) -> Result<(), String> {
+    if minutes == 0 {
+        return Err(format!("{} has to be at least 1 minute", key));
+    }
+    Ok(())
+}
+
+fn check_repository_settings(settings: &RepositorySettings) -> Result<(), String> {
+    if let Some(frequency) = settings.backup_frequency {
+        check_minutes("backup_frequency", frequency)?;
+    }
+    if let Some(buffer_time) = settings.change_detection_buffer {
+        check_minutes("change_detection_buffer", buffer_time)?;
+    }
+    if let Some(template) = &settings.branch_name_template {
+        check_branch_name_template(template)?;
+    }
+    if let Some(url) = &settings.backup_url {
+        check_backup_url(url)?;
+    }
+    Ok(())
+}
+
+//A typo would otherwise only show up as a failed push in the daemon
+fn check_backup_url(url: &str) -> Result<(), String> {
+    match git2_api_wrapper::get_auth_type_from_url(url.trim()) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!(
+            "{} is not a supported backup url, use an https or ssh url, or a local path",
+            url
+        )),
+    }
+}
+
+fn check_backup_ref_namespace(namespace: &str) -> Result<(), String> {
+    let is_valid = namespace.starts_with("refs/")
+        && git2::Reference::is_valid_name(&format!("{}/host/branch", namespace));
+    if !is_valid {
+        return Err(format!(
+            "{} is not a valid reference namespace, it should look like refs/commitpal",
+            namespace
+        ));
+    }
+    Ok(())
+}
+
+fn check_secret_rule(rule: &SecretRule) -> Result<(), String> {
+    if rule.path.is_none() && rule.pattern.is_none() {
+        return Err(format!(
+            "Secret rule {} needs a path, a pattern or both",
+            rule.name
+        ));
+    }
+    if let Some(pattern) = &rule.pattern {
+        if let Err(e) = Regex::new(pattern) {
+            return Err(format!(
+                "{} is not a valid regular expression: {}",
+                pattern, e
+            ));
+        }
+    }
+    Ok(())
+}
+
+//The checks of the dedicated set commands, for the values that serde accepts but CommitPal cannot use
+fn validate_config(config: &Config) -> Result<(), String> {
+    if config.version != CONFIG_VERSION {
+        return Err(format!(
+            "The version is managed by CommitPal, it should stay {}",
+            CONFIG_VERSION
+        ));
+    }
+    check_minutes("backup_frequency", config.backup_frequency)?;
+    check_minutes("change_detection_buffer", config.change_detection_buffer)?;
+    check_backup_ref_namespace(&config.backup_ref_namespace)?;
+    check_branch_name_template(&config.branch_name_template)?;
+    if let Some(url) = &config.backup_url {
+        check_backup_url(url)?;
+    }
+    for (folder, settings) in &config.watching_folders {
+        if let Err(e) = check_repository_settings(settings) {
+            return Err(format!("{}: {}", folder, e));
+        }
+    }
+    if config.obfuscate_paths && !config.encrypt_backups {
+        return Err("obfuscate_paths needs encrypt_backups".to_string());
+    }
+    for rule in &config.secret_rules {
+        check_secret_rule(rule)?;
+    }
+    Ok(())
+}
+
 pub fn set_inited() {
+    let _lock = lock_config();
     let mut config = read_config();
     config.is_inited = true;
-    if let Err(e) = write_config(config) {
+    if let Err(e) = write_config(&config) {
         panic!("Failed to set inited: {}", e);
     }
 }
@@ -93,61 +833,220 @@ pub fn get_inited() -> bool {
     read_config().is_inited
 }
 
-//Read-only, by the executor
+//Held around every read-modify-write of the config, so concurrent commands do not lose each other's changes
+//The lock is released when the file is dropped, readers do not need it as the config is replaced atomically
+fn lock_config() -> File {
+    let lock_path = match cross_platform_constant::get_config_path() {
+        Ok(config_path) => format!("{}.lock", config_path),
+        Err(e) => panic!("Failed to get config path: {}", e),
+    };
+
+    if !is_path_exist(&lock_path) {
+        if let Err(e) = create_file_recursively(&lock_path) {
+            panic!("Failed to create the config lock file: {}", e);
+        }
+    }
+
+    let lock_file = match OpenOptions::new().write(true).open(&lock_path) {
+        Ok(lock_file) => lock_file,
+        Err(e) => panic!("Failed to open the config lock file: {}", e),
+    };
+    if let Err(e) = lock_file.lock_exclusive() {
+        panic!("Failed to lock the config file: {}", e);
+    }
+
+    lock_file
+}
+
+//A config that cannot be read stops the command, it is never replaced by the defaults
 pub fn read_config() -> Config {
-    //Step 1: Get Config Path for different os
+    match try_read_config() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+//Read-only, an older config is upgraded in memory until migrate_config writes it
+pub fn try_read_config() -> Result<Config, String> {
+    read_config_file().map(|(config, _, _)| config)
+}
+
+//Upgrade the config file to CONFIG_VERSION, run once before every command but init
+//A config.json from an older CommitPal is converted to config.toml
+pub fn migrate_config() -> Result<(), String> {
+    let _lock = lock_config();
+    let (config, version, is_legacy) = read_config_file()?;
+
     let config_path = match cross_platform_constant::get_config_path() {
         Ok(config_path) => config_path,
-        Err(e) => {
-            panic!("Failed to get config path: {}", e)
+        Err(e) => return Err(format!("Failed to get config path: {}", e)),
+    };
+
+    if is_legacy {
+        let legacy_config_path = match cross_platform_constant::get_legacy_config_path() {
+            Ok(legacy_config_path) => legacy_config_path,
+            Err(e) => return Err(format!("Failed to get config path: {}", e)),
+        };
+        if let Err(e) = write_config(&config) {
+            return Err(format!("Failed to convert the config file: {}", e));
+        }
+        let backup_path = format!("{}.bak", legacy_config_path);
+        match rename_file(&legacy_config_path, &backup_path) {
+            Ok(_) => println!(
+                "Converted the config file to {}, the old file is kept at {}",
+                config_path, backup_path
+            ),
+            Err(e) => println!(
+                "Converted the config file to {}, but failed to move the old file away: {}",
+                config_path, e
+            ),
+        }
+    } else if version < CONFIG_VERSION {
+        //Kept for good, the backup made by write_config is replaced on the next write
+        let backup_path = format!("{}.v{}.bak", config_path, version);
+        if let Err(e) = copy_file(&config_path, &backup_path) {
+            return Err(format!(
+                "Failed to back up the config file before migrating it: {}",
+                e
+            ));
         }
+        if let Err(e) = write_config(&config) {
+            return Err(format!("Failed to write the migrated config file: {}", e));
+        }
+        println!(
+            "Migrated the config file from version {} to {}, the old file is kept at {}",
+            version, CONFIG_VERSION, backup_path
+        );
+    }
+
+    Ok(())
+}
+
+//Returns the config at CONFIG_VERSION, the version it was written in and whether it is a config.json
+fn read_config_file() -> Result<(Config, u64, bool), String> {
+    //Step 1: Get Config Path for different os, a config.json from an older CommitPal is still read
+    let config_path = match cross_platform_constant::get_config_path() {
+        Ok(config_path) => config_path,
+        Err(e) => return Err(format!("Failed to get config path: {}", e)),
+    };
+    let legacy_config_path = match cross_platform_constant::get_legacy_config_path() {
+        Ok(legacy_config_path) => legacy_config_path,
+        Err(e) => return Err(format!("Failed to get config path: {}", e)),
     };
 
-    if !is_path_exist(&config_path) {
-        return Config::new();
+    let is_legacy = !is_path_exist(&config_path) && is_path_exist(&legacy_config_path);
+    let read_path = if is_legacy {
+        &legacy_config_path
+    } else {
+        &config_path
+    };
+    if !is_path_exist(read_path) {
+        return Ok((Config::new(), CONFIG_VERSION, false));
     }
 
-    let s = match read_file_to_string(&config_path) {
+    let s = match read_file_to_string(read_path) {
         Ok(s) => s,
-        Err(e) => {
-            panic!("Failed to read the config file : {}", e)
-        }
+        Err(e) => return Err(format!("Failed to read the config file : {}", e)),
     };
 
     if s.is_empty() {
-        return Config::new();
+        return Ok((Config::new(), CONFIG_VERSION, false));
     }
 
-    match serde_json::from_str(&s) {
-        Ok(config) => config,
-        Err(e) => {
-            println!("Failed to parse the config file : {}", e);
-            Config::new()
+    match parse_config(&s, is_legacy) {
+        Ok((config, version)) => Ok((config, version, is_legacy)),
+        Err(e) => Err(format!(
+            "The config file {} {}. Please fix it, or run the init command to start over",
+            read_path, e
+        )),
+    }
+}
+
+//Returns the config at CONFIG_VERSION along with the version it was written in
+fn parse_config(s: &str, is_json: bool) -> Result<(Config, u64), String> {
+    let config: Value = if is_json {
+        match serde_json::from_str(s) {
+            Ok(config) => config,
+            Err(e) => return Err(format!("is corrupt: {}", e)),
         }
+    } else {
+        match toml::from_str(s) {
+            Ok(config) => config,
+            Err(e) => return Err(format!("is corrupt: {}", e)),
+        }
+    };
+    let version = match config_migrator::get_version(&config) {
+        Ok(version) => version,
+        Err(e) => return Err(format!("is corrupt: {}", e)),
+    };
+    let config = match config_migrator::migrate(config) {
+        Ok(config) => config,
+        Err(e) => return Err(format!("cannot be read: {}", e)),
+    };
+    match serde_json::from_value(config) {
+        Ok(config) => Ok((config, version)),
+        Err(e) => Err(format!("is corrupt: {}", e)),
     }
 }
 
-fn write_config(config: Config) -> Result<(), String> {
+//The previous file is kept at config.toml.bak
+fn write_config(config: &Config) -> Result<(), String> {
+    match config_schema::to_commented_toml(config) {
+        Ok(config_toml) => write_config_file(config_toml),
+        Err(e) => Err(e),
+    }
+}
+
+fn write_config_file(content: String) -> Result<(), String> {
     //Step 1: Get Config Path for different os
     let config_path = match cross_platform_constant::get_config_path() {
         Ok(config_path) => config_path,
         Err(e) => return Err(format!("Failed to get config path: {}", e)),
     };
 
-    //Step 2: Check if the config path is exist, if not create one
+    //Step 2: Check if the config path is exist, if not create one, otherwise back it up
     if !is_path_exist(&config_path) {
         match create_file_recursively(&config_path) {
             Ok(_) => println!("Created a new config file"),
             Err(e) => return Err(format!("Failed to create a new directory: {}", e)),
         };
+    } else if let Err(e) = copy_file(&config_path, &format!("{}.bak", config_path)) {
+        return Err(format!("Failed to back up the config file: {}", e));
     }
 
-    //Step 3: Serialise config to json
-    let config_json = match serde_json::to_string(&config) {
-        Ok(config_json) => config_json,
-        Err(e) => return Err(format!("Failed to serialise config to json: {}", e)),
-    };
+    //Step 3: Write the content to the config file
+    write_string_to_file(&config_path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn branch_name_templates_need_a_separator() {
+        assert!(check_branch_name_template("{host}/{branch}").is_ok());
+        assert!(check_branch_name_template("{branch}/by-{host}").is_ok());
+        assert!(check_branch_name_template("backup/{host}/nested/{branch}").is_ok());
 
-    //Step 4: Write the json to the config file
-    write_string_to_file(&config_path, config_json)
+        assert!(check_branch_name_template("{branch}_{host}").is_err());
+        assert!(check_branch_name_template("{host}/{host}").is_err());
+        assert!(check_branch_name_template("{branch}").is_err());
+        assert!(check_branch_name_template("{host}/../{branch}").is_err());
+    }
+
+    #[test]
+    fn backup_urls_need_a_supported_scheme() {
+        assert!(check_backup_url("https://github.com/owner/{repo}.git").is_ok());
+        assert!(check_backup_url("git@github.com:owner/repo.git").is_ok());
+        assert!(check_backup_url("ssh://git@nas/backup.git").is_ok());
+        assert!(check_backup_url("/mnt/nas/backup.git").is_ok());
+        assert!(check_backup_url("../backup.git").is_ok());
+
+        assert!(check_backup_url("http://github.com/owner/repo.git").is_err());
+        assert!(check_backup_url("ftp://nas/backup.git").is_err());
+        assert!(check_backup_url("").is_err());
+    }
 }
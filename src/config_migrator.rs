@@ -0,0 +1,125 @@
+use crate::data_structures::config::CONFIG_VERSION;
+use serde_json::{Map, Value};
+
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+//MIGRATIONS[n] upgrades a config of version n to version n + 1
+const MIGRATIONS: &[Migration] = &[migrate_v0_to_v1];
+
+//Configs written before the version field was added are version 0
+pub fn get_version(config: &Value) -> Result<u64, String> {
+    match config.get("version") {
+        None => Ok(0),
+        Some(version) => match version.as_u64() {
+            Some(version) => Ok(version),
+            None => Err(format!("the version {} is not a number", version)),
+        },
+    }
+}
+
+//Returns the config at CONFIG_VERSION, a config from a newer CommitPal is refused so it is not downgraded
+pub fn migrate(mut config: Value) -> Result<Value, String> {
+    let version = get_version(&config)?;
+    if version > CONFIG_VERSION {
+        return Err(format!(
+            "it is version {}, this CommitPal only supports up to version {}, please upgrade CommitPal",
+            version, CONFIG_VERSION
+        ));
+    }
+
+    let fields = match config.as_object_mut() {
+        Some(fields) => fields,
+        None => return Err("it is not a JSON object".to_string()),
+    };
+
+    for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        if let Err(e) = migration(fields) {
+            return Err(format!(
+                "failed to migrate it from version {} to {}: {}",
+                from,
+                from + 1,
+                e
+            ));
+        }
+        fields.insert("version".to_string(), Value::from(from as u64 + 1));
+    }
+
+    Ok(config)
+}
+
+//watching_folders was a list of paths, each one becomes a folder with the default settings
+fn migrate_v0_to_v1(config: &mut Map<String, Value>) -> Result<(), String> {
+    let paths = match config.remove("watching_folders") {
+        Some(Value::Array(paths)) => paths,
+        None => Vec::new(),
+        Some(watching_folders) => {
+            return Err(format!(
+                "watching_folders {} is not a list of paths",
+                watching_folders
+            ))
+        }
+    };
+
+    let mut watching_folders = Map::new();
+    for path in paths {
+        match path {
+            Value::String(path) => {
+                watching_folders.insert(path, Value::Object(Map::new()));
+            }
+            _ => return Err(format!("{} is not a watched folder path", path)),
+        }
+    }
+
+    config.insert(
+        "watching_folders".to_string(),
+        Value::Object(watching_folders),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn version_0_watched_folders_become_settings() {
+        let config = json!({
+            "watching_folders": ["/repo/a", "/repo/b"],
+            "backup_frequency": 5,
+            "change_detection_buffer": 1,
+            "is_inited": true
+        });
+
+        let migrated = migrate(config).unwrap();
+        assert_eq!(
+            migrated,
+            json!({
+                "version": CONFIG_VERSION,
+                "backup_frequency": 5,
+                "change_detection_buffer": 1,
+                "is_inited": true,
+                "watching_folders": { "/repo/a": {}, "/repo/b": {} }
+            })
+        );
+    }
+
+    #[test]
+    fn current_configs_are_kept() {
+        let config = json!({
+            "version": CONFIG_VERSION,
+            "watching_folders": { "/repo/a": { "paused": true } }
+        });
+
+        assert_eq!(migrate(config.clone()).unwrap(), config);
+    }
+
+    #[test]
+    fn unreadable_configs_are_refused() {
+        assert!(migrate(json!({ "version": CONFIG_VERSION + 1 })).is_err());
+        assert!(migrate(json!({ "version": "one" })).is_err());
+        assert!(migrate(json!(["/repo/a"])).is_err());
+        assert!(migrate(json!({ "watching_folders": "/repo/a" })).is_err());
+        assert!(migrate(json!({ "watching_folders": { "/repo/a": {} } })).is_err());
+    }
+}
@@ -0,0 +1,242 @@
+use crate::data_structures::config::Config;
+use toml_edit::{DocumentMut, Item, Key, Table};
+
+const FILE_HEADER: &str = "# CommitPal config
+# Change it with `commitpal config set <key> <value>`, or `commitpal config edit` to have the changes checked
+# The comments are rewritten whenever CommitPal saves the file
+";
+
+//Every key of the config, * stands for the path of a watched folder
+//The comments of the keys outside watching_folders are written into the config file
+const CONFIG_KEYS: &[(&str, &str)] = &[
+    (
+        "version",
+        "The format of this file, it is upgraded by CommitPal",
+    ),
+    ("is_inited", "Set by the init command"),
+    (
+        "backup_frequency",
+        "How often a repository is backed up, in minutes",
+    ),
+    (
+        "change_detection_buffer",
+        "How long a repository has to stay unchanged before it is backed up, in minutes",
+    ),
+    (
+        "backup_branch_mode",
+        "Timestamped: a new backup per snapshot, Rolling: one backup per host and branch on top of the previous one",
+    ),
+    (
+        "backup_ref_namespace",
+        "Where the backups are pushed, refs/commitpal keeps them out of the branch list, refs/heads/backup pushes them as branches",
+    ),
+    (
+        "branch_name_template",
+        "The name of a backup after the namespace, {host} and {branch} are replaced and need a / between them",
+    ),
+    (
+        "backup_url",
+        "Used by every repository without a backup url or remote of its own, {repo} is replaced by the folder name",
+    ),
+    (
+        "local_only",
+        "Only store the snapshots in the repositories, the push-local command uploads them later",
+    ),
+    (
+        "encrypt_backups",
+        "Encrypt the file contents with the key in the keyring before they are pushed",
+    ),
+    ("obfuscate_paths", "Encrypt the file and folder names as well"),
+    (
+        "snapshot_policy",
+        "Which files go into a snapshot: TrackedOnly, Untracked or Everything",
+    ),
+    (
+        "secret_scan_policy",
+        "What happens when the changes contain secrets: Off, Warn, Redact or Block",
+    ),
+    (
+        "retention",
+        "How many timestamped backups are kept on the remote, nothing is pruned without it. Rolling backups are never pruned",
+    ),
+    (
+        "retention.keep_last",
+        "Always keep the latest N backups of each host and branch",
+    ),
+    (
+        "retention.keep_daily_days",
+        "Keep the latest backup of each day for the last D days",
+    ),
+    (
+        "commit_identity",
+        "The author of the backups of repositories without user.name and user.email",
+    ),
+    ("commit_identity.name", "The name of the author"),
+    ("commit_identity.email", "The email of the author"),
+    (
+        "secret_rules",
+        "Checked along with the built-in secret detectors, each rule has a name, a path (.gitignore syntax), a pattern (regular expression) or both",
+    ),
+    (
+        "watching_folders",
+        "The watched repositories, each one can override backup_frequency, change_detection_buffer, backup_remote, backup_url, branch_name_template, snapshot_policy and commit_identity, or be paused",
+    ),
+    ("watching_folders.*", "A watched repository"),
+    (
+        "watching_folders.*.paused",
+        "A paused repository is neither watched nor backed up",
+    ),
+    (
+        "watching_folders.*.backup_frequency",
+        "How often the repository is backed up, in minutes",
+    ),
+    (
+        "watching_folders.*.change_detection_buffer",
+        "How long the repository has to stay unchanged before it is backed up, in minutes",
+    ),
+    (
+        "watching_folders.*.backup_remote",
+        "The name of the remote the backups go to",
+    ),
+    (
+        "watching_folders.*.backup_url",
+        "A dedicated backup url, it takes precedence over the remote",
+    ),
+    (
+        "watching_folders.*.branch_name_template",
+        "The name of a backup after the namespace, {host} and {branch} are replaced and need a / between them",
+    ),
+    (
+        "watching_folders.*.snapshot_policy",
+        "Which files go into a snapshot: TrackedOnly, Untracked or Everything",
+    ),
+    (
+        "watching_folders.*.commit_identity",
+        "The author of the backups, it overrides the git config of the repository",
+    ),
+    ("watching_folders.*.commit_identity.name", "The name of the author"),
+    (
+        "watching_folders.*.commit_identity.email",
+        "The email of the author",
+    ),
+];
+
+//Splits a key such as watching_folders."/home/me/repo".paused, a folder path has to be quoted
+//Backslashes are escapes in double quotes, so a Windows path goes in single quotes, e.g. watching_folders.'C:\Users\me\repo'.paused
+pub fn parse_key(key: &str) -> Result<Vec<String>, String> {
+    let path: Vec<String> = match Key::parse(key) {
+        Ok(keys) => keys.iter().map(|key| key.get().to_string()).collect(),
+        Err(e) => return Err(format!("{} is not a valid key: {}", key, e)),
+    };
+
+    if find_comment(&path).is_none() {
+        let known_keys: Vec<String> = CONFIG_KEYS
+            .iter()
+            .map(|(pattern, _)| pattern.replace('*', "\"<folder>\""))
+            .collect();
+        return Err(format!(
+            "Unknown key {}, the keys are:\n{}\nA folder is quoted like in TOML, in single quotes if it has backslashes, e.g. watching_folders.'C:\\Users\\me\\repo'.paused",
+            key,
+            known_keys.join("\n")
+        ));
+    }
+
+    Ok(path)
+}
+
+fn find_comment(path: &[String]) -> Option<&'static str> {
+    CONFIG_KEYS
+        .iter()
+        .find(|(pattern, _)| {
+            let parts: Vec<&str> = pattern.split('.').collect();
+            parts.len() == path.len()
+                && parts
+                    .iter()
+                    .zip(path)
+                    .all(|(part, key)| *part == "*" || part == key)
+        })
+        .map(|(_, comment)| *comment)
+}
+
+//The settings that are not set are left out of the file
+pub fn to_commented_toml(config: &Config) -> Result<String, String> {
+    let toml = match toml::to_string(config) {
+        Ok(toml) => toml,
+        Err(e) => return Err(format!("Failed to serialise config to toml: {}", e)),
+    };
+    let mut document = match toml.parse::<DocumentMut>() {
+        Ok(document) => document,
+        Err(e) => return Err(format!("Failed to serialise config to toml: {}", e)),
+    };
+
+    comment_keys(document.as_table_mut());
+
+    Ok(format!("{}{}", FILE_HEADER, document))
+}
+
+fn comment_keys(table: &mut Table) {
+    for (mut key, item) in table.iter_mut() {
+        let comment = match find_comment(&[key.get().to_string()]) {
+            Some(comment) => format!("\n# {}\n", comment),
+            None => continue,
+        };
+
+        match item {
+            //An implicit table has no header, e.g. watching_folders only has the headers of its folders
+            Item::Table(table) if table.is_implicit() => {
+                if let Some((_, Item::Table(first))) = table.iter_mut().next() {
+                    first.decor_mut().set_prefix(comment);
+                }
+            }
+            Item::Table(table) => table.decor_mut().set_prefix(comment),
+            Item::ArrayOfTables(tables) => {
+                if let Some(first) = tables.iter_mut().next() {
+                    first.decor_mut().set_prefix(comment);
+                }
+            }
+            _ => key.leaf_decor_mut().set_prefix(comment),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_split_like_toml() {
+        assert_eq!(
+            parse_key("backup_frequency").unwrap(),
+            vec!["backup_frequency"]
+        );
+        assert_eq!(
+            parse_key("retention.keep_last").unwrap(),
+            vec!["retention", "keep_last"]
+        );
+        assert_eq!(
+            parse_key("watching_folders.\"/home/me/my.repo\".paused").unwrap(),
+            vec!["watching_folders", "/home/me/my.repo", "paused"]
+        );
+    }
+
+    #[test]
+    fn windows_folders_are_read_from_literal_strings() {
+        assert_eq!(
+            parse_key(r"watching_folders.'C:\Users\me\repo'.backup_url").unwrap(),
+            vec!["watching_folders", r"C:\Users\me\repo", "backup_url"]
+        );
+        assert_eq!(
+            parse_key(r#"watching_folders."C:\\Users\\me\\repo".paused"#).unwrap(),
+            vec!["watching_folders", r"C:\Users\me\repo", "paused"]
+        );
+    }
+
+    #[test]
+    fn unknown_and_invalid_keys_are_refused() {
+        assert!(parse_key("backup_frequencies").is_err());
+        assert!(parse_key("watching_folders.\"/home/me/repo\".unknown").is_err());
+        assert!(parse_key("watching_folders./home/me/repo.paused").is_err());
+        //\U starts a unicode escape in a basic string
+        assert!(parse_key(r#"watching_folders."C:\Users\me\repo".paused"#).is_err());
+    }
+}
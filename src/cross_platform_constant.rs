@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 const APP_NAME: &str = "CommitPal";
-const CONFIG_FILE_NAME: &str = "config.json";
+const CONFIG_FILE_NAME: &str = "config.toml";
+//Read once and converted to CONFIG_FILE_NAME
+const LEGACY_CONFIG_FILE_NAME: &str = "config.json";
 
 #[cfg(target_os = "windows")]
 fn get_config_path_prefix() -> String {
@@ -46,10 +48,18 @@ fn expand_home_path(path: &str) -> String {
 }
 
 pub fn get_config_path() -> Result<String, String> {
+    get_config_file_path(CONFIG_FILE_NAME)
+}
+
+pub fn get_legacy_config_path() -> Result<String, String> {
+    get_config_file_path(LEGACY_CONFIG_FILE_NAME)
+}
+
+fn get_config_file_path(file_name: &str) -> Result<String, String> {
     let config_path_prefix = &get_config_path_prefix();
     let mut path = PathBuf::from(config_path_prefix);
     path.push(APP_NAME);
-    path.push(CONFIG_FILE_NAME);
+    path.push(file_name);
 
     match path.to_str() {
         Some(p) => Ok(p.to_string()),
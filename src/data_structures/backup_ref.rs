@@ -0,0 +1,13 @@
+use chrono::NaiveDateTime;
+use git2::Oid;
+
+//A backup reference found on the remote, named <namespace>/<host>/<branch>[_<time>]
+#[derive(Clone)]
+pub struct BackupRef {
+    pub ref_name: String,
+    pub host: String,
+    pub source_branch: String,
+    //Only timestamped backups carry their creation time in the name
+    pub created_at: Option<NaiveDateTime>,
+    pub commit_id: Oid,
+}
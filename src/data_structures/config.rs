@@ -1,40 +1,299 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use std::str::FromStr;
 
+//Bump it with a new migration in config_migrator when a change cannot be read with serde defaults alone
+pub const CONFIG_VERSION: u64 = 1;
 //This is in minutes
 const DEFAULT_BACKUP_FREQUENCY: u64 = 30;
 const DEFAULT_CHANGE_DETECTION_BUFFER: u64 = 1;
+//Hidden from the branch list, clones and CI, refs/heads/backup pushes the backups as branches instead
+pub const DEFAULT_BACKUP_REF_NAMESPACE: &str = "refs/commitpal";
+//Signs the backups of repositories without user.name and user.email in their git config
+const DEFAULT_COMMIT_NAME: &str = "Auto Git Bot";
+const DEFAULT_COMMIT_EMAIL: &str = "makeup@gmail.com";
+//Replaced by the folder name of the repository in the global backup url
+pub const BACKUP_URL_REPO_PLACEHOLDER: &str = "{repo}";
+//Replaced by the host name and the source branch in the name of a backup, placed after the namespace
+pub const BRANCH_NAME_HOST_PLACEHOLDER: &str = "{host}";
+pub const BRANCH_NAME_BRANCH_PLACEHOLDER: &str = "{branch}";
+pub const DEFAULT_BRANCH_NAME_TEMPLATE: &str = "{host}/{branch}";
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum BackupBranchMode {
+    //A new <namespace>/<host>/<branch>_<time> reference for every backup
+    #[default]
+    Timestamped,
+    //One <namespace>/<host>/<branch> reference, each backup is stacked on top of the previous one
+    Rolling,
+}
+
+impl FromStr for BackupBranchMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "timestamped" => Ok(BackupBranchMode::Timestamped),
+            "rolling" => Ok(BackupBranchMode::Rolling),
+            _ => Err(format!(
+                "Unknown backup branch mode {}, expected timestamped or rolling",
+                s
+            )),
+        }
+    }
+}
+
+//Which files of the working tree go into a snapshot, .commitpalignore can include or exclude more
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum SnapshotPolicy {
+    //Only the changes of tracked files
+    TrackedOnly,
+    //Tracked files and untracked files that are not ignored
+    #[default]
+    Untracked,
+    //Every file including the ignored ones, except the ones excluded by .commitpalignore
+    //Ignored folders such as target or node_modules are skipped unless .commitpalignore includes them
+    Everything,
+}
+
+impl FromStr for SnapshotPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tracked-only" => Ok(SnapshotPolicy::TrackedOnly),
+            "untracked" => Ok(SnapshotPolicy::Untracked),
+            "everything" => Ok(SnapshotPolicy::Everything),
+            _ => Err(format!(
+                "Unknown snapshot policy {}, expected tracked-only, untracked or everything",
+                s
+            )),
+        }
+    }
+}
+
+//What happens to a backup when the changed files contain secrets
+//Unpushed commits and stash entries cannot be redacted, they are blocked with Redact too
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum SecretScanPolicy {
+    #[default]
+    Off,
+    //Back up as usual and notify about the secrets
+    Warn,
+    //The offending files keep the content of HEAD in the snapshot, new ones are left out
+    Redact,
+    //No backup is made until the secrets are removed
+    Block,
+}
+
+impl FromStr for SecretScanPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(SecretScanPolicy::Off),
+            "warn" => Ok(SecretScanPolicy::Warn),
+            "redact" => Ok(SecretScanPolicy::Redact),
+            "block" => Ok(SecretScanPolicy::Block),
+            _ => Err(format!(
+                "Unknown secret scan policy {}, expected off, warn, redact or block",
+                s
+            )),
+        }
+    }
+}
+
+//A user rule matches a path, a content pattern, or both when both are set
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SecretRule {
+    pub name: String,
+    //Same syntax as .gitignore, e.g. *.pem
+    #[serde(default)]
+    pub path: Option<String>,
+    //A regular expression matched against each line
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CommitIdentity {
+    pub name: String,
+    pub email: String,
+}
+
+//The settings of one watched repository, every override that is not set falls back to the global one
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RepositorySettings {
+    //A paused repository is neither watched nor backed up
+    #[serde(default)]
+    pub paused: bool,
+    //In minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_frequency: Option<u64>,
+    //In minutes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub change_detection_buffer: Option<u64>,
+    //The name of the remote the backups go to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_remote: Option<String>,
+    //A dedicated backup url, it takes precedence over the remote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_name_template: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot_policy: Option<SnapshotPolicy>,
+    //Overrides the git config of the repository
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_identity: Option<CommitIdentity>,
+}
+
+//Applied per host and source branch, backups matching neither rule are deleted from the remote
+//Only timestamped backups are pruned, a rolling backup is one reference whose history is kept as a whole
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct RetentionPolicy {
+    //Always keep the latest N backups
+    pub keep_last: usize,
+    //Keep the latest backup of each day for the last D days
+    pub keep_daily_days: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
-    pub watching_folders: HashSet<String>,
+    //Written by config_migrator, 0 for configs older than the version field
+    #[serde(default)]
+    pub version: u64,
+    //Repository path to its settings, sorted so the config file keeps its order
+    pub watching_folders: BTreeMap<String, RepositorySettings>,
     pub backup_frequency: u64,
     pub change_detection_buffer: u64,
     pub is_inited: bool,
+    #[serde(default)]
+    pub backup_branch_mode: BackupBranchMode,
+    #[serde(default = "default_backup_ref_namespace")]
+    pub backup_ref_namespace: String,
+    //Nothing is pruned unless a retention policy is set
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+    //The name of a backup after the namespace, {host} and {branch} are replaced
+    #[serde(default = "default_branch_name_template")]
+    pub branch_name_template: String,
+    //Used by every repository without its own backup url or remote, e.g. /mnt/nas/backups/{repo}.git
+    #[serde(default)]
+    pub backup_url: Option<String>,
+    //Snapshots are only stored in the repository, they are uploaded later by the push-local command
+    #[serde(default)]
+    pub local_only: bool,
+    //File contents are encrypted with the key in the keyring before they are pushed
+    #[serde(default)]
+    pub encrypt_backups: bool,
+    //File and folder names are encrypted as well
+    #[serde(default)]
+    pub obfuscate_paths: bool,
+    #[serde(default)]
+    pub snapshot_policy: SnapshotPolicy,
+    //Used when the repository has no user.name and user.email in its git config
+    #[serde(default = "default_commit_identity")]
+    pub commit_identity: CommitIdentity,
+    #[serde(default)]
+    pub secret_scan_policy: SecretScanPolicy,
+    //Checked along with the built-in detectors
+    #[serde(default)]
+    pub secret_rules: Vec<SecretRule>,
+}
+
+fn default_commit_identity() -> CommitIdentity {
+    CommitIdentity {
+        name: DEFAULT_COMMIT_NAME.to_string(),
+        email: DEFAULT_COMMIT_EMAIL.to_string(),
+    }
+}
+
+fn default_backup_ref_namespace() -> String {
+    DEFAULT_BACKUP_REF_NAMESPACE.to_string()
+}
+
+fn default_branch_name_template() -> String {
+    DEFAULT_BRANCH_NAME_TEMPLATE.to_string()
 }
 
 impl Config {
     pub fn new() -> Config {
         Config {
-            watching_folders: HashSet::new(),
+            version: CONFIG_VERSION,
+            watching_folders: BTreeMap::new(),
             backup_frequency: DEFAULT_BACKUP_FREQUENCY,
             change_detection_buffer: DEFAULT_CHANGE_DETECTION_BUFFER,
             is_inited: false,
+            backup_branch_mode: BackupBranchMode::default(),
+            backup_ref_namespace: default_backup_ref_namespace(),
+            retention: None,
+            branch_name_template: default_branch_name_template(),
+            backup_url: None,
+            local_only: false,
+            encrypt_backups: false,
+            obfuscate_paths: false,
+            snapshot_policy: SnapshotPolicy::default(),
+            commit_identity: default_commit_identity(),
+            secret_scan_policy: SecretScanPolicy::default(),
+            secret_rules: Vec::new(),
         }
     }
 
+    pub fn get_repository_settings(&self, folder: &str) -> Option<&RepositorySettings> {
+        self.watching_folders.get(folder)
+    }
+
+    //The watched folders that are not paused
+    pub fn get_active_folders(&self) -> HashSet<String> {
+        self.watching_folders
+            .iter()
+            .filter(|(_, settings)| !settings.paused)
+            .map(|(folder, _)| folder.clone())
+            .collect()
+    }
+
+    pub fn get_backup_frequency(&self, folder: &str) -> u64 {
+        self.get_repository_settings(folder)
+            .and_then(|settings| settings.backup_frequency)
+            .unwrap_or(self.backup_frequency)
+    }
+
+    pub fn get_change_detection_buffer(&self, folder: &str) -> u64 {
+        self.get_repository_settings(folder)
+            .and_then(|settings| settings.change_detection_buffer)
+            .unwrap_or(self.change_detection_buffer)
+    }
+
+    pub fn get_snapshot_policy(&self, folder: &str) -> SnapshotPolicy {
+        self.get_repository_settings(folder)
+            .and_then(|settings| settings.snapshot_policy)
+            .unwrap_or(self.snapshot_policy)
+    }
+
+    pub fn get_branch_name_template(&self, folder: &str) -> &str {
+        self.get_repository_settings(folder)
+            .and_then(|settings| settings.branch_name_template.as_deref())
+            .unwrap_or(&self.branch_name_template)
+    }
+
     pub fn insert_watching_folder(&mut self, folder: String) {
-        if self.watching_folders.contains(&folder) {
-            println!("{} is already being watched", folder);
+        if let Some(settings) = self.watching_folders.get_mut(&folder) {
+            if settings.paused {
+                settings.paused = false;
+                println!("{} is no longer paused", folder);
+            } else {
+                println!("{} is already being watched", folder);
+            }
             return;
         }
 
-        //But actually nothing will happen if you add an existing folder
-        self.watching_folders.insert(folder);
+        self.watching_folders
+            .insert(folder, RepositorySettings::default());
     }
 
     pub fn remove_watching_folder(&mut self, folder: &str) {
-        if !self.watching_folders.contains(folder) {
+        if !self.watching_folders.contains_key(folder) {
             println!("{} is not being watched", folder);
             return;
         }
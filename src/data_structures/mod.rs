@@ -1 +1,4 @@
+pub mod backup_ref;
 pub mod config;
+pub mod pending_snapshot;
+pub mod secret_finding;
@@ -0,0 +1,9 @@
+use chrono::{DateTime, Local};
+use git2::Oid;
+
+//A snapshot stored in the repository that has not been pushed yet
+pub struct PendingSnapshot {
+    pub source_branch: String,
+    pub snapshot_id: Oid,
+    pub created_at: DateTime<Local>,
+}
@@ -0,0 +1,19 @@
+use std::fmt;
+
+//A secret found in a changed file of a snapshot
+pub struct SecretFinding {
+    pub path: String,
+    //The built-in detector or the user rule that matched
+    pub detector: String,
+    //None when a path rule matched the whole file
+    pub line: Option<usize>,
+}
+
+impl fmt::Display for SecretFinding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{} ({})", self.path, line, self.detector),
+            None => write!(f, "{} ({})", self.path, self.detector),
+        }
+    }
+}
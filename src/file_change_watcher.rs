@@ -1,11 +1,12 @@
-use crate::config_manager;
+use crate::cross_platform_constant;
+use crate::data_structures::config::Config;
+use crate::shared_config::SharedConfig;
 use chrono::{DateTime, Utc};
-use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
 use std::collections::HashSet;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::thread;
-use std::time::Duration;
 
 //This whole file watcher script will be run in a separate thread
 
@@ -23,22 +24,42 @@ impl FileChangeSignal {
     }
 }
 
+//Changes to the config file reload the shared config, every other change is sent to the executor
 fn create_file_watcher(
     tx: Sender<FileChangeSignal>,
+    shared_config: SharedConfig,
+    config_path: PathBuf,
 ) -> Result<notify::RecommendedWatcher, notify::Error> {
     let tx = tx.clone();
-    notify::recommended_watcher(move |res| match res {
-        Ok(event) => on_file_change_event(event, tx.clone()),
+    notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            if event.paths.contains(&config_path) {
+                //Reading the config file raises access events, reloading on them would never end
+                if !matches!(event.kind, EventKind::Access(_)) {
+                    shared_config.reload();
+                }
+            } else {
+                on_file_change_event(event, tx.clone());
+            }
+        }
         Err(e) => println!("watch error: {:?}", e),
     })
 }
 
+//The folder is watched instead of the file, as the file is replaced on every write
+fn watch_config_file(watcher: &mut RecommendedWatcher, config_path: &Path) -> notify::Result<()> {
+    match config_path.parent() {
+        Some(config_folder) => watcher.watch(config_folder, RecursiveMode::NonRecursive),
+        None => Ok(()),
+    }
+}
+
 fn update_watching_repo(
     watcher: &mut RecommendedWatcher,
     previous_watching_folder: &mut HashSet<String>,
+    config: &Config,
 ) -> notify::Result<()> {
-    let config = config_manager::read_config();
-    let current_watching_folder = config.watching_folders.iter().cloned().collect();
+    let current_watching_folder = config.get_active_folders();
 
     for folder in previous_watching_folder.difference(&current_watching_folder) {
         watcher.unwatch(Path::new(&folder))?;
@@ -68,9 +89,21 @@ fn on_file_change_event(event: Event, tx: Sender<FileChangeSignal>) {
     tx.send(signal).unwrap();
 }
 
-pub fn start(tx: Sender<FileChangeSignal>) {
+pub fn start(tx: Sender<FileChangeSignal>, shared_config: SharedConfig) {
     thread::spawn(move || {
-        let mut watcher = match create_file_watcher(tx) {
+        let config_path = match cross_platform_constant::get_config_path() {
+            Ok(config_path) => PathBuf::from(config_path),
+            Err(e) => {
+                println!("start watcher failed");
+                println!("Error: {}", e);
+                return;
+            }
+        };
+        //Subscribed before the current config is read, so no change is missed in between
+        let config_updates = shared_config.subscribe();
+
+        let mut watcher = match create_file_watcher(tx, shared_config.clone(), config_path.clone())
+        {
             Ok(watcher) => watcher,
             Err(e) => {
                 println!("start watcher failed");
@@ -78,14 +111,27 @@ pub fn start(tx: Sender<FileChangeSignal>) {
                 return;
             }
         };
+        if let Err(e) = watch_config_file(&mut watcher, &config_path) {
+            println!("watch config file failed, changes to it need a restart");
+            println!("Error: {:?}", e);
+        }
+
         let mut watched_folder: HashSet<String> = HashSet::new();
+        let mut snapshot = shared_config.get();
         loop {
-            if let Err(e) = update_watching_repo(&mut watcher, &mut watched_folder) {
+            if let Err(e) =
+                update_watching_repo(&mut watcher, &mut watched_folder, &snapshot.config)
+            {
                 println!("watch repository failed");
                 println!("Error: {:?}", e);
                 return;
             }
-            std::thread::sleep(Duration::from_millis(5000));
+
+            //Blocks until the config changes
+            snapshot = match config_updates.recv() {
+                Ok(snapshot) => snapshot,
+                Err(_) => return,
+            };
         }
     });
 }
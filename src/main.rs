@@ -1,17 +1,32 @@
+mod backup_encryptor;
 mod backup_executor;
+mod backup_message;
+mod backup_pruner;
+mod backup_restorer;
 mod config_manager;
+mod config_migrator;
+mod config_schema;
 mod cross_platform_constant;
 mod data_structures;
 mod file_change_watcher;
 mod gitignore_wrapper;
 mod repository_instance;
-mod temp_clone_repo;
+mod secret_scanner;
+mod shared_config;
+mod snapshot_repo;
+mod snapshot_rules;
 mod tool_initialiser;
 mod utilities;
+use backup_restorer::RestoreMode;
+use data_structures::config::{
+    BackupBranchMode, CommitIdentity, RetentionPolicy, SecretRule, SecretScanPolicy, SnapshotPolicy,
+};
 use single_instance::SingleInstance;
+use snapshot_repo::SnapshotRepo;
+use std::process;
 use structopt::StructOpt;
 use utilities::notification_service;
-use utilities::{file_system, secret_manager};
+use utilities::{commit_signer, file_system, git2_api_wrapper, secret_manager};
 
 const APP_NAME: &str = "CommitPal";
 #[derive(StructOpt)]
@@ -26,19 +41,28 @@ enum Command {
     Init,
     #[structopt(about = "Watches a Folder")]
     Add {
-        #[structopt(help = "The folder to backup when it is updated")]
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The folder to backup when it is updated"
+        )]
         folder: String,
     },
     #[structopt(about = "Watch all the git repo in this folder (not recursive)")]
     AddWorkspace {
-        #[structopt(help = "The root folder to watch all the git repos in it")]
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The root folder to watch all the git repos in it"
+        )]
         folder: String,
     },
     #[structopt(about = "List the watched folders")]
     List,
     #[structopt(about = "Remove a watched folder")]
     Remove {
-        #[structopt(help = "The folder to remove from the watch list")]
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The folder to remove from the watch list"
+        )]
         folder: String,
     },
     #[structopt(about = "Remove all the watched folders")]
@@ -61,21 +85,335 @@ enum Command {
     DeletePAT,
     #[structopt(about = "Set the frequency of the backup")]
     SetBackupFreq {
-        #[structopt(help = "The frequency of the backup in minutes")]
-        frequency: u64,
+        #[structopt(
+            help = "The frequency of the backup in minutes, leave it out to remove the override of --folder"
+        )]
+        frequency: Option<u64>,
+        #[structopt(
+            long,
+            parse(from_str = file_system::get_absolute_path),
+            help = "Only use the frequency for this repository, otherwise it is used for every repository"
+        )]
+        folder: Option<String>,
     },
     #[structopt(
         about = "How long should the file considered as changed after the last change (in minutes)"
     )]
     SetChangeBuffer {
-        #[structopt(help = "The buffer time in minutes")]
-        buffer_time: u64,
+        #[structopt(
+            help = "The buffer time in minutes, leave it out to remove the override of --folder"
+        )]
+        buffer_time: Option<u64>,
+        #[structopt(
+            long,
+            parse(from_str = file_system::get_absolute_path),
+            help = "Only use the buffer time for this repository, otherwise it is used for every repository"
+        )]
+        folder: Option<String>,
     },
+    #[structopt(about = "Stop backing up a watched folder, its settings are kept")]
+    Pause {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The folder to pause"
+        )]
+        folder: String,
+    },
+    #[structopt(about = "Back up a paused folder again")]
+    Unpause {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The folder to unpause"
+        )]
+        folder: String,
+    },
+    #[structopt(
+        about = "Set how backup branches are named: timestamped (a new branch per backup) or rolling (one branch per host and branch)"
+    )]
+    SetBranchMode {
+        #[structopt(help = "timestamped or rolling")]
+        mode: BackupBranchMode,
+    },
+    #[structopt(
+        about = "Set the reference namespace backups are pushed to, e.g. refs/heads/backup to push them as branches"
+    )]
+    SetRefNamespace {
+        #[structopt(help = "The reference namespace, default is refs/commitpal")]
+        namespace: String,
+    },
+    #[structopt(about = "Set how backups are named after the reference namespace")]
+    SetBranchNameTemplate {
+        #[structopt(
+            help = "The template with {host} and {branch} separated by a /, default is {host}/{branch}. Leave it out to remove the override of --folder"
+        )]
+        template: Option<String>,
+        #[structopt(
+            long,
+            parse(from_str = file_system::get_absolute_path),
+            help = "Only use the template for this repository, otherwise it is used for every repository"
+        )]
+        folder: Option<String>,
+    },
+    #[structopt(about = "List the backups of a repository on its remote")]
+    ListBackups {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository to list the backups of"
+        )]
+        folder: String,
+        #[structopt(long, help = "Fetch the backups and verify their signatures")]
+        verify: bool,
+    },
+    #[structopt(about = "Restore a backup of the current branch into a repository")]
+    Restore {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository to restore the backup into"
+        )]
+        folder: String,
+        #[structopt(
+            long,
+            help = "The number of the backup to restore, you will be asked to choose one if it is not provided"
+        )]
+        backup: Option<usize>,
+        #[structopt(
+            long,
+            default_value = "worktree",
+            help = "How to restore the backup: worktree, branch or stash"
+        )]
+        mode: RestoreMode,
+        #[structopt(
+            long,
+            help = "Restore from the snapshots stored in the repository instead of the remote"
+        )]
+        local: bool,
+        #[structopt(long, help = "Only restore the backup if it has a good signature")]
+        verify: bool,
+    },
+    #[structopt(
+        about = "Merge the latest backup of the current branch made by another machine into a repository"
+    )]
+    Resume {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository to resume the work in"
+        )]
+        folder: String,
+        #[structopt(long, help = "Only merge the backup if it has a good signature")]
+        verify: bool,
+    },
+    #[structopt(
+        about = "Set how many timestamped backups are kept on the remote, rolling backups are never pruned"
+    )]
+    SetRetention {
+        #[structopt(help = "Always keep the latest N backups of each host and branch")]
+        keep_last: usize,
+        #[structopt(help = "Keep the latest backup of each day for the last D days")]
+        keep_daily_days: u64,
+    },
+    #[structopt(about = "Stop pruning backups, all of them will be kept")]
+    DisableRetention,
+    #[structopt(
+        about = "Delete the backups of this machine that are not covered by the retention policy"
+    )]
+    Prune {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository to prune, all the watched folders if it is not provided"
+        )]
+        folder: Option<String>,
+        #[structopt(long, help = "Only list the backups that would be deleted")]
+        dry_run: bool,
+        #[structopt(long, help = "Also prune the backups made by other machines")]
+        all_hosts: bool,
+    },
+    #[structopt(about = "Set the remote the backups of a repository are pushed to")]
+    SetRemote {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository to set the backup remote of"
+        )]
+        folder: String,
+        #[structopt(
+            help = "The name of the remote, the upstream remote of the current branch is used if it is not provided"
+        )]
+        remote: Option<String>,
+    },
+    #[structopt(
+        about = "Push backups to a dedicated url instead of a remote of the repository, e.g. a private mirror or a bare repo on a NAS"
+    )]
+    SetBackupUrl {
+        #[structopt(
+            help = "The url or path, a relative path is relative to the repository. {repo} is replaced by the repository folder name. The url is removed if it is not provided"
+        )]
+        url: Option<String>,
+        #[structopt(
+            long,
+            parse(from_str = file_system::get_absolute_path),
+            help = "Only use the url for this repository, otherwise it is used for every repository"
+        )]
+        folder: Option<String>,
+    },
+    #[structopt(
+        about = "Only store the snapshots in the repositories without pushing them, e.g. when working offline"
+    )]
+    SetLocalOnly {
+        #[structopt(parse(try_from_str), help = "true or false")]
+        enabled: bool,
+    },
+    #[structopt(
+        about = "Encrypt the file contents of the backups before they are pushed, the key is set with set-encryption-key"
+    )]
+    SetEncryption {
+        #[structopt(parse(try_from_str), help = "true or false")]
+        enabled: bool,
+        #[structopt(long, help = "Encrypt the file and folder names as well")]
+        obfuscate_paths: bool,
+    },
+    #[structopt(about = "Store the key used to encrypt and decrypt the backups")]
+    SetEncryptionKey {
+        #[structopt(help = "The encryption key, the same key is needed on every machine")]
+        key: String,
+    },
+    #[structopt(about = "Delete the stored encryption key")]
+    DeleteEncryptionKey,
+    #[structopt(
+        about = "Write the files of an encrypted backup of the current branch into a folder"
+    )]
+    Decrypt {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository the backup belongs to"
+        )]
+        folder: String,
+        #[structopt(help = "The empty or new folder to write the files into")]
+        output: String,
+        #[structopt(
+            long,
+            help = "The number of the backup to decrypt, you will be asked to choose one if it is not provided"
+        )]
+        backup: Option<usize>,
+    },
+    #[structopt(about = "Push the snapshots stored in a repository that have not been pushed yet")]
+    PushLocal {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository to push, all the watched folders if it is not provided"
+        )]
+        folder: Option<String>,
+    },
+    #[structopt(about = "List the snapshots waiting to be pushed")]
+    Queue {
+        #[structopt(
+            parse(from_str = file_system::get_absolute_path),
+            help = "The repository to check, all the watched folders if it is not provided"
+        )]
+        folder: Option<String>,
+    },
+    #[structopt(
+        about = "Set which files go into a snapshot, .commitpalignore in the repository can include or exclude more"
+    )]
+    SetSnapshotPolicy {
+        #[structopt(help = "tracked-only, untracked (the default) or everything")]
+        policy: SnapshotPolicy,
+        #[structopt(
+            long,
+            parse(from_str = file_system::get_absolute_path),
+            help = "Only use the policy for this repository, otherwise it is used for every repository"
+        )]
+        folder: Option<String>,
+    },
+    #[structopt(about = "Set what happens when the changes to back up contain secrets")]
+    SetSecretScanPolicy {
+        #[structopt(help = "off (the default), warn, redact or block")]
+        policy: SecretScanPolicy,
+    },
+    #[structopt(
+        about = "Add a rule to the secret scan, a file matches when it matches the path and the pattern that are set"
+    )]
+    AddSecretRule {
+        #[structopt(help = "The name shown when the rule matches")]
+        name: String,
+        #[structopt(
+            long,
+            help = "The files to report, same syntax as .gitignore, e.g. *.pem"
+        )]
+        path: Option<String>,
+        #[structopt(long, help = "A regular expression matched against each line")]
+        pattern: Option<String>,
+    },
+    #[structopt(about = "Remove a rule from the secret scan")]
+    RemoveSecretRule {
+        #[structopt(help = "The name of the rule")]
+        name: String,
+    },
+    #[structopt(
+        about = "Set the author of the backup commits, by default it is user.name and user.email of the repository"
+    )]
+    SetIdentity {
+        #[structopt(
+            help = "The name of the author, leave both out to remove the override of --folder"
+        )]
+        name: Option<String>,
+        #[structopt(help = "The email of the author")]
+        email: Option<String>,
+        #[structopt(
+            long,
+            parse(from_str = file_system::get_absolute_path),
+            help = "Override the git config of this repository, otherwise it is the fallback for repositories without one"
+        )]
+        folder: Option<String>,
+    },
+    #[structopt(about = "Get or change any setting of the config file")]
+    Config(ConfigCommand),
+}
+
+#[derive(StructOpt)]
+enum ConfigCommand {
+    #[structopt(about = "Print a setting, or the whole config file without a key")]
+    Get {
+        #[structopt(
+            help = "The key of the setting, e.g. backup_frequency, watching_folders.\"/path/to/repo\".paused or watching_folders.'C:\\path\\to\\repo'.paused"
+        )]
+        key: Option<String>,
+    },
+    #[structopt(about = "Change a setting, the value is checked before it is saved")]
+    Set {
+        #[structopt(
+            help = "The key of the setting, e.g. backup_frequency, watching_folders.\"/path/to/repo\".paused or watching_folders.'C:\\path\\to\\repo'.paused"
+        )]
+        key: String,
+        #[structopt(
+            help = "A TOML value, e.g. 10, true, Rolling or \"{ keep_last = 3, keep_daily_days = 7 }\""
+        )]
+        value: String,
+    },
+    #[structopt(
+        about = "Reset a setting to its default, or remove the override of a watched folder"
+    )]
+    Unset {
+        #[structopt(
+            help = "The key of the setting, e.g. backup_frequency, watching_folders.\"/path/to/repo\".paused or watching_folders.'C:\\path\\to\\repo'.paused"
+        )]
+        key: String,
+    },
+    #[structopt(
+        about = "Open the config file in $VISUAL or $EDITOR, it is saved once it is valid"
+    )]
+    Edit,
 }
 
 fn main() {
     let args = Cli::from_args();
 
+    //Init starts over, so a config it replaces does not have to be readable
+    if !matches!(args.cmd, Command::Init) {
+        if let Err(e) = config_manager::migrate_config() {
+            println!("{}", e);
+            process::exit(1);
+        }
+    }
+
     match args.cmd {
         Command::Init => {
             tool_initialiser::init();
@@ -142,11 +480,326 @@ fn main() {
             Ok(_) => println!("Personal Access Token is deleted successfully!"),
             Err(e) => println!("Failed to delete the personal access token: {}", e),
         },
-        Command::SetBackupFreq { frequency } => {
-            config_manager::set_backup_frequency(frequency);
+        Command::SetBackupFreq { frequency, folder } => {
+            config_manager::set_backup_frequency(folder.as_deref(), frequency);
+        }
+        Command::SetChangeBuffer {
+            buffer_time,
+            folder,
+        } => {
+            config_manager::set_change_buffer_time(folder.as_deref(), buffer_time);
+        }
+        Command::Pause { folder } => {
+            config_manager::set_paused(&folder, true);
+        }
+        Command::Unpause { folder } => {
+            config_manager::set_paused(&folder, false);
+        }
+        Command::SetBranchMode { mode } => {
+            config_manager::set_backup_branch_mode(mode);
+        }
+        Command::SetRefNamespace { namespace } => {
+            config_manager::set_backup_ref_namespace(&namespace);
+        }
+        Command::SetBranchNameTemplate { template, folder } => {
+            config_manager::set_branch_name_template(folder.as_deref(), template.as_deref());
+        }
+        Command::ListBackups { folder, verify } => {
+            let snapshot_repo = match SnapshotRepo::new(&folder) {
+                Ok(snapshot_repo) => snapshot_repo,
+                Err(e) => {
+                    println!("Failed to open {}: {}", folder, e);
+                    return;
+                }
+            };
+            let backups = match snapshot_repo.list_backups(None) {
+                Ok(backups) => backups,
+                Err(e) => {
+                    println!("Failed to list the backups of {}: {}", folder, e);
+                    return;
+                }
+            };
+            let commit_backups = match snapshot_repo.list_commit_backups() {
+                Ok(commit_backups) => commit_backups,
+                Err(e) => {
+                    println!("Failed to list the unpushed commits of {}: {}", folder, e);
+                    return;
+                }
+            };
+            let stash_backups = match snapshot_repo.list_stash_backups() {
+                Ok(stash_backups) => stash_backups,
+                Err(e) => {
+                    println!("Failed to list the stash entries of {}: {}", folder, e);
+                    return;
+                }
+            };
+            if backups.is_empty() && commit_backups.is_empty() && stash_backups.is_empty() {
+                println!("No backup is found for {}", folder);
+                return;
+            }
+
+            //The signatures are in the commits, so the backups that are not available locally are fetched first
+            if verify {
+                let missing_ref_names: Vec<&str> = backups
+                    .iter()
+                    .filter(|backup| snapshot_repo.repo.find_commit(backup.commit_id).is_err())
+                    .map(|backup| backup.ref_name.as_str())
+                    .collect();
+                if !missing_ref_names.is_empty() {
+                    if let Err(e) = snapshot_repo.remote().and_then(|remote| {
+                        git2_api_wrapper::fetch_remote_refs(
+                            &snapshot_repo.repo,
+                            &missing_ref_names,
+                            remote,
+                        )
+                    }) {
+                        println!("Failed to fetch the backups of {}: {}", folder, e);
+                        return;
+                    }
+                }
+            }
+
+            println!("Found {} backups:", backups.len());
+            for backup in backups {
+                let signature = if verify {
+                    format!(
+                        ", {}",
+                        commit_signer::verify_commit(&snapshot_repo.repo, backup.commit_id)
+                    )
+                } else {
+                    String::new()
+                };
+                println!(
+                    "{} {} (host: {}, branch: {}{})",
+                    backup.commit_id, backup.ref_name, backup.host, backup.source_branch, signature
+                );
+            }
+
+            if !commit_backups.is_empty() {
+                println!(
+                    "Found {} branches with unpushed commits:",
+                    commit_backups.len()
+                );
+                for (ref_name, commit_id) in commit_backups {
+                    println!("{} {}", commit_id, ref_name);
+                }
+            }
+
+            if !stash_backups.is_empty() {
+                println!("Found {} stash entries:", stash_backups.len());
+                for (ref_name, stash_id) in stash_backups {
+                    let message = snapshot_repo
+                        .repo
+                        .find_commit(stash_id)
+                        .map(|stash| stash.summary().unwrap_or_default().to_string())
+                        .unwrap_or_default();
+                    println!("{} {}", ref_name, message);
+                }
+            }
+        }
+        Command::Restore {
+            folder,
+            backup,
+            mode,
+            local,
+            verify,
+        } => {
+            if let Err(e) = backup_restorer::restore(&folder, backup, mode, local, verify) {
+                println!("{}", e);
+            }
+        }
+        Command::Resume { folder, verify } => {
+            if let Err(e) = backup_restorer::resume(&folder, verify) {
+                println!("{}", e);
+            }
+        }
+        Command::SetRetention {
+            keep_last,
+            keep_daily_days,
+        } => {
+            config_manager::set_retention_policy(Some(RetentionPolicy {
+                keep_last,
+                keep_daily_days,
+            }));
+        }
+        Command::DisableRetention => {
+            config_manager::set_retention_policy(None);
+        }
+        Command::Prune {
+            folder,
+            dry_run,
+            all_hosts,
+        } => {
+            let config = config_manager::read_config();
+            let folders = match folder {
+                Some(folder) => vec![folder],
+                None => config.watching_folders.keys().cloned().collect(),
+            };
+
+            for folder in folders {
+                match backup_pruner::prune(&folder, &config, dry_run, all_hosts) {
+                    Ok(pruned) if pruned.is_empty() => println!("Nothing to prune in {}", folder),
+                    Ok(pruned) => {
+                        let action = if dry_run { "Would delete" } else { "Deleted" };
+                        println!("{} {} backups of {}:", action, pruned.len(), folder);
+                        for ref_name in pruned {
+                            println!("{}", ref_name);
+                        }
+                    }
+                    Err(e) => println!("Failed to prune {}: {}", folder, e),
+                }
+            }
+        }
+        Command::SetRemote { folder, remote } => {
+            config_manager::set_backup_remote(&folder, remote.as_deref());
+        }
+        Command::SetBackupUrl { url, folder } => {
+            config_manager::set_backup_url(folder.as_deref(), url.as_deref());
+        }
+        Command::SetLocalOnly { enabled } => {
+            config_manager::set_local_only(enabled);
+        }
+        Command::SetEncryption {
+            enabled,
+            obfuscate_paths,
+        } => {
+            config_manager::set_encryption(enabled, obfuscate_paths);
+        }
+        Command::SetEncryptionKey { key } => match secret_manager::set_encryption_key(&key) {
+            Ok(_) => println!("Encryption key is stored successfully!"),
+            Err(e) => println!("Failed to store the encryption key: {}", e),
+        },
+        Command::DeleteEncryptionKey => match secret_manager::delete_encryption_key() {
+            Ok(_) => println!("Encryption key is deleted successfully!"),
+            Err(e) => println!("Failed to delete the encryption key: {}", e),
+        },
+        Command::Decrypt {
+            folder,
+            output,
+            backup,
+        } => {
+            if let Err(e) = backup_restorer::decrypt(&folder, backup, &output) {
+                println!("{}", e);
+            }
+        }
+        Command::PushLocal { folder } => {
+            let folders = match folder {
+                Some(folder) => vec![folder],
+                None => config_manager::read_config()
+                    .watching_folders
+                    .into_keys()
+                    .collect(),
+            };
+
+            for folder in folders {
+                let snapshot_repo = match SnapshotRepo::new(&folder) {
+                    Ok(snapshot_repo) => snapshot_repo,
+                    Err(e) => {
+                        println!("Failed to open {}: {}", folder, e);
+                        continue;
+                    }
+                };
+                match snapshot_repo.push_pending_snapshots() {
+                    Ok(count) => println!("Pushed {} snapshots of {}", count, folder),
+                    Err(e) => {
+                        println!("Failed to push the snapshots of {}: {}", folder, e);
+                        continue;
+                    }
+                }
+                match snapshot_repo.push_unpushed_commits() {
+                    Ok(count) => println!(
+                        "Pushed the unpushed commits of {} branches of {}",
+                        count, folder
+                    ),
+                    Err(e) => println!("Failed to push the unpushed commits of {}: {}", folder, e),
+                }
+                match snapshot_repo.push_stashes() {
+                    Ok(count) => println!("Pushed {} stash entries of {}", count, folder),
+                    Err(e) => println!("Failed to push the stash entries of {}: {}", folder, e),
+                }
+            }
+        }
+        Command::Queue { folder } => {
+            let folders = match folder {
+                Some(folder) => vec![folder],
+                None => config_manager::read_config()
+                    .watching_folders
+                    .into_keys()
+                    .collect(),
+            };
+
+            for folder in folders {
+                let pending_snapshots = match SnapshotRepo::new(&folder)
+                    .and_then(|repo| repo.get_pending_snapshots())
+                {
+                    Ok(pending_snapshots) => pending_snapshots,
+                    Err(e) => {
+                        println!("Failed to read the queue of {}: {}", folder, e);
+                        continue;
+                    }
+                };
+
+                println!(
+                    "{} snapshots of {} are waiting to be pushed",
+                    pending_snapshots.len(),
+                    folder
+                );
+                for pending_snapshot in pending_snapshots {
+                    println!(
+                        "{} {} (branch: {})",
+                        pending_snapshot.created_at.format("%Y-%m-%d %H:%M:%S"),
+                        pending_snapshot.snapshot_id,
+                        pending_snapshot.source_branch
+                    );
+                }
+            }
+        }
+        Command::SetSnapshotPolicy { policy, folder } => {
+            config_manager::set_snapshot_policy(folder.as_deref(), policy);
+        }
+        Command::SetSecretScanPolicy { policy } => {
+            config_manager::set_secret_scan_policy(policy);
+        }
+        Command::AddSecretRule {
+            name,
+            path,
+            pattern,
+        } => {
+            config_manager::add_secret_rule(SecretRule {
+                name,
+                path,
+                pattern,
+            });
+        }
+        Command::RemoveSecretRule { name } => {
+            config_manager::remove_secret_rule(&name);
+        }
+        Command::SetIdentity {
+            name,
+            email,
+            folder,
+        } => {
+            let identity = match (name, email) {
+                (Some(name), Some(email)) => Some(CommitIdentity { name, email }),
+                (None, None) => None,
+                _ => {
+                    println!("Please provide both the name and the email");
+                    return;
+                }
+            };
+            config_manager::set_commit_identity(folder.as_deref(), identity);
+        }
+        Command::Config(ConfigCommand::Get { key }) => {
+            config_manager::get_config_value(key.as_deref());
+        }
+        Command::Config(ConfigCommand::Set { key, value }) => {
+            config_manager::set_config_value(&key, &value);
+        }
+        Command::Config(ConfigCommand::Unset { key }) => {
+            config_manager::unset_config_value(&key);
         }
-        Command::SetChangeBuffer { buffer_time } => {
-            config_manager::set_change_buffer_time(buffer_time);
+        Command::Config(ConfigCommand::Edit) => {
+            config_manager::edit_config();
         }
     }
 }
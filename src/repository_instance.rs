@@ -1,12 +1,13 @@
-use crate::config_manager;
+use crate::data_structures::config::Config;
 use crate::utilities::file_system;
-use crate::{gitignore_wrapper::GitIgnoreWrapper, temp_clone_repo::TempCloneRepo};
+use crate::{gitignore_wrapper::GitIgnoreWrapper, snapshot_repo::SnapshotRepo};
 use chrono::{DateTime, Utc};
 use std::path::Path;
 
 pub struct RepositoryInstance {
     repo_path: String,
     last_update_time: Option<DateTime<Utc>>,
+    last_backup_time: Option<DateTime<Utc>>,
     dirty: bool,
     git_ignore: GitIgnoreWrapper,
 }
@@ -26,31 +27,26 @@ impl RepositoryInstance {
         Ok(RepositoryInstance {
             repo_path: repo_path.to_string(),
             last_update_time: None,
+            last_backup_time: None,
             dirty: false, //by default, it is not dirty
             git_ignore,
         })
     }
 
-    pub fn try_perform_backup(&mut self) -> Result<(), git2::Error> {
-        if !self.should_perform_backup() {
+    pub fn try_perform_backup(&mut self, config: &Config) -> Result<(), git2::Error> {
+        if !self.should_perform_backup(config) {
             println!("No need to perform backup for {}", self.repo_path);
             return Ok(());
         }
-        let mut temp_clone_repo = TempCloneRepo::new(&self.repo_path)?;
+        let snapshot_repo = SnapshotRepo::with_config(&self.repo_path, config.clone())?;
 
-        let result = temp_clone_repo.perform_backup();
-
-        match temp_clone_repo.clean_temp_clone_folder() {
-            Ok(_) => {}
-            Err(e) => {
-                println!("Failed to delete the temp clone repo: {}", e);
-            }
-        }
-
-        match result {
-            Ok(_) => {
-                println!("Backup done for {}", self.repo_path);
+        match snapshot_repo.perform_backup() {
+            Ok(snapshot_id) => {
+                if snapshot_id.is_some() {
+                    println!("Backup done for {}", self.repo_path);
+                }
                 self.last_update_time = None;
+                self.last_backup_time = Some(Utc::now());
                 self.dirty = false;
                 Ok(())
             }
@@ -74,19 +70,37 @@ impl RepositoryInstance {
         self.last_update_time = Some(date_time);
     }
 
-    fn should_perform_backup(&self) -> bool {
+    fn should_perform_backup(&self, config: &Config) -> bool {
         if !self.dirty {
             println!("{} is not dirty", self.repo_path);
             return false;
         }
 
-        let config = config_manager::read_config();
+        let current_time = Utc::now();
+
+        //The executor wakes up for the most frequent repository, this one may have a longer frequency
+        if let Some(last_backup_time) = self.last_backup_time {
+            let backup_frequency = if cfg!(debug_assertions) {
+                5
+            } else {
+                config.get_backup_frequency(&self.repo_path) * 60
+            } as i64;
+            if current_time
+                .signed_duration_since(last_backup_time)
+                .num_seconds()
+                < backup_frequency
+            {
+                println!(
+                    "{} was backed up less than {} seconds ago",
+                    self.repo_path, backup_frequency
+                );
+                return false;
+            }
+        }
 
         match self.last_update_time {
             None => false,
             Some(last_update_time) => {
-                let current_time = Utc::now();
-
                 let duration = current_time
                     .signed_duration_since(last_update_time)
                     .num_seconds();
@@ -94,7 +108,7 @@ impl RepositoryInstance {
                 let change_detection_buffer = if cfg!(debug_assertions) {
                     5
                 } else {
-                    config.change_detection_buffer * 60
+                    config.get_change_detection_buffer(&self.repo_path) * 60
                 } as i64;
                 println!("Duration = {}", duration);
                 println!("Change detection buffer = {}", change_detection_buffer);
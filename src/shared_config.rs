@@ -0,0 +1,109 @@
+use crate::data_structures::config::Config;
+use crate::utilities::notification_service;
+use crate::{config_manager, cross_platform_constant};
+use std::fs;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+
+//One revision of the config, it is replaced as a whole when the config file changes
+pub struct ConfigSnapshot {
+    pub revision: u64,
+    pub config: Config,
+}
+
+//The config of the daemon, loaded once and reloaded when the file change watcher sees the config file change
+#[derive(Clone)]
+pub struct SharedConfig {
+    current: Arc<RwLock<Arc<ConfigSnapshot>>>,
+    subscribers: Arc<Mutex<Vec<Sender<Arc<ConfigSnapshot>>>>>,
+}
+
+impl SharedConfig {
+    pub fn load() -> SharedConfig {
+        let snapshot = ConfigSnapshot {
+            revision: 0,
+            config: config_manager::read_config(),
+        };
+
+        SharedConfig {
+            current: Arc::new(RwLock::new(Arc::new(snapshot))),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub fn get(&self) -> Arc<ConfigSnapshot> {
+        match self.current.read() {
+            Ok(current) => current.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        }
+    }
+
+    //The receiver gets every revision after the current one
+    pub fn subscribe(&self) -> Receiver<Arc<ConfigSnapshot>> {
+        let (tx, rx) = mpsc::channel();
+        match self.subscribers.lock() {
+            Ok(mut subscribers) => subscribers.push(tx),
+            Err(poisoned) => poisoned.into_inner().push(tx),
+        }
+        rx
+    }
+
+    //A config that cannot be read keeps the previous revision, e.g. while the file is being edited by hand
+    pub fn reload(&self) {
+        //An editor rewriting the file in place empties it first, which would read as the default config
+        if is_config_file_empty() {
+            return;
+        }
+
+        let config = match config_manager::try_read_config() {
+            Ok(config) => config,
+            Err(e) => {
+                println!(
+                    "Failed to reload the config, the previous one is kept: {}",
+                    e
+                );
+                notification_service::show_notification(
+                    "Failed to reload the config".to_string(),
+                    format!("The previous config is kept: {}", e),
+                );
+                return;
+            }
+        };
+
+        let snapshot = {
+            let mut current = match self.current.write() {
+                Ok(current) => current,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+
+            //The config file is also touched when it is backed up or saved without changes
+            if serde_json::to_value(&config).ok() == serde_json::to_value(&current.config).ok() {
+                return;
+            }
+
+            let snapshot = Arc::new(ConfigSnapshot {
+                revision: current.revision + 1,
+                config,
+            });
+            *current = snapshot.clone();
+            snapshot
+        };
+        println!("The config is reloaded, revision {}", snapshot.revision);
+
+        let mut subscribers = match self.subscribers.lock() {
+            Ok(subscribers) => subscribers,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        //Subscribers that are gone are dropped
+        subscribers.retain(|subscriber| subscriber.send(snapshot.clone()).is_ok());
+    }
+}
+
+fn is_config_file_empty() -> bool {
+    match cross_platform_constant::get_config_path() {
+        Ok(config_path) => fs::metadata(config_path)
+            .map(|metadata| metadata.len() == 0)
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,973 @@
+use chrono::{DateTime, Local};
+use git2::{Commit, Oid, Repository, Signature, Tree};
+use std::{cell::OnceCell, path::Path};
+
+use crate::{
+    backup_encryptor, backup_executor, backup_message, config_manager,
+    data_structures::{
+        backup_ref::BackupRef,
+        config::{
+            BackupBranchMode, Config, SecretScanPolicy, BACKUP_URL_REPO_PLACEHOLDER,
+            DEFAULT_BRANCH_NAME_TEMPLATE,
+        },
+        pending_snapshot::PendingSnapshot,
+    },
+    secret_scanner::SecretScanner,
+    snapshot_rules::SnapshotRules,
+    utilities::{
+        file_system,
+        git2_api_wrapper::{self, AuthType, BackupRemote, RemoteTarget},
+        notification_service,
+    },
+};
+
+const STASH_REF_NAME: &str = "refs/stash";
+//The source branch in the message of an encrypted stash entry
+const STASH_SOURCE_BRANCH: &str = "stash";
+
+//The snapshot is built straight from the live working directory
+//Nothing is copied, and the index, HEAD and stash of the repository are never touched
+pub struct SnapshotRepo {
+    pub repo: Repository,
+    pub path: String,
+    remote: OnceCell<BackupRemote>,
+    config: Config,
+}
+
+impl SnapshotRepo {
+    pub fn new(repo_path: &str) -> Result<SnapshotRepo, git2::Error> {
+        Self::with_config(repo_path, config_manager::read_config())
+    }
+
+    //Used by the daemon, which keeps the config in memory instead of reading the file
+    pub fn with_config(repo_path: &str, config: Config) -> Result<SnapshotRepo, git2::Error> {
+        let repo = Repository::open(repo_path)?;
+
+        Ok(SnapshotRepo {
+            repo,
+            path: repo_path.to_string(),
+            remote: OnceCell::new(),
+            config,
+        })
+    }
+
+    //The remote is only resolved when it is used, so snapshots and local-only mode work without one
+    pub fn remote(&self) -> Result<&BackupRemote, git2::Error> {
+        if let Some(remote) = self.remote.get() {
+            return Ok(remote);
+        }
+
+        let target = match resolve_backup_remote(&self.repo, &self.path, &self.config) {
+            Ok(target) => target,
+            Err(e) => {
+                return Err(git2::Error::from_str(&format!(
+                    "Failed to choose the backup remote: {}",
+                    e
+                )));
+            }
+        };
+
+        let auth_type = match get_auth_type(&self.repo, &target) {
+            Ok(auth_type) => auth_type,
+            Err(e) => {
+                return Err(git2::Error::from_str(&format!(
+                    "Failed to get the auth type: {}",
+                    e
+                )));
+            }
+        };
+
+        println!(
+            "{} is backed up to {}, a {:?} remote",
+            self.path, target, auth_type
+        );
+
+        Ok(self
+            .remote
+            .get_or_init(|| BackupRemote { target, auth_type }))
+    }
+
+    //Returns the id of the snapshot, None if nothing changed since the last one or the secret scan blocked it
+    pub fn perform_backup(&self) -> Result<Option<Oid>, git2::Error> {
+        //Keep the reference of the current branch
+        let current_branch = git2_api_wrapper::get_current_branch_name(&self.repo)?;
+
+        let head = self.repo.head()?.peel_to_commit()?;
+        let index_tree_id = git2_api_wrapper::build_index_tree(&self.repo)?;
+        let tree_id = self.build_snapshot_tree()?;
+
+        //A touch or a save without any change still marks the repository dirty
+        //The last snapshot of the branch is compared along with what was staged and the base commit
+        let local_ref_name = backup_executor::get_local_back_up_ref_name(&current_branch);
+        if let Some(last_snapshot_id) =
+            self.find_unchanged_snapshot(&local_ref_name, &head, index_tree_id, tree_id)?
+        {
+            println!(
+                "Skip the backup of {} on {}, nothing changed since snapshot {}",
+                self.path, current_branch, last_snapshot_id
+            );
+            return Ok(None);
+        }
+
+        //Only scanned once something changed, so an unchanged repository is not reported again
+        let (index_tree_id, tree_id, secret_warning) =
+            match self.scan_for_secrets(&head, index_tree_id, tree_id)? {
+                Some(scanned) => scanned,
+                None => return Ok(None),
+            };
+
+        if let Some(secret_warning) = secret_warning {
+            //The redacted trees can be the same as the last snapshot
+            if let Some(last_snapshot_id) =
+                self.find_unchanged_snapshot(&local_ref_name, &head, index_tree_id, tree_id)?
+            {
+                println!(
+                    "Skip the backup of {} on {}, nothing but the redacted files changed since snapshot {}",
+                    self.path, current_branch, last_snapshot_id
+                );
+                return Ok(None);
+            }
+
+            println!("{}", secret_warning);
+            notification_service::show_notification(
+                "Secrets found in the backup".to_string(),
+                secret_warning,
+            );
+        }
+
+        let signature = self.get_signature()?;
+
+        //Recorded the same way git stash does, so what was staged can be told apart from what was not
+        let index_commit_id = git2_api_wrapper::commit_all_changes(
+            &self.repo,
+            index_tree_id,
+            &[&head],
+            &format!(
+                "index on {}: {} {}",
+                current_branch,
+                head.id(),
+                head.summary().unwrap_or_default()
+            ),
+            &signature,
+        )?;
+        let index_commit = self.repo.find_commit(index_commit_id)?;
+
+        let message = backup_message::build_backup_message(
+            &self.repo,
+            &current_branch,
+            &head,
+            &self.repo.find_tree(tree_id)?,
+        )?;
+
+        let commit_id = git2_api_wrapper::commit_all_changes(
+            &self.repo,
+            tree_id,
+            &[&head, &index_commit],
+            &message,
+            &signature,
+        )?;
+
+        //The snapshot is stored locally first, so it is not lost if the push fails
+        git2_api_wrapper::update_local_ref(
+            &self.repo,
+            &local_ref_name,
+            commit_id,
+            "CommitPal snapshot",
+        )?;
+        println!(
+            "Snapshot {} of {} is stored in {}",
+            commit_id, self.path, local_ref_name
+        );
+
+        //Pushing is a separate stage, the snapshot stays pending until the outbox is flushed
+        Ok(Some(commit_id))
+    }
+
+    //Returns the last snapshot of the branch if it has the same trees and base commit
+    fn find_unchanged_snapshot(
+        &self,
+        local_ref_name: &str,
+        head: &Commit,
+        index_tree_id: Oid,
+        tree_id: Oid,
+    ) -> Result<Option<Oid>, git2::Error> {
+        let last_snapshot = match self
+            .repo
+            .find_reference(local_ref_name)
+            .and_then(|reference| reference.peel_to_commit())
+        {
+            Ok(last_snapshot) => last_snapshot,
+            Err(_) => return Ok(None),
+        };
+
+        let (last_head, last_index_commit) = git2_api_wrapper::get_backup_parts(&last_snapshot)?;
+        let is_unchanged = last_snapshot.tree_id() == tree_id
+            && last_head.id() == head.id()
+            && last_index_commit.map(|index_commit| index_commit.tree_id()) == Some(index_tree_id);
+
+        Ok(is_unchanged.then(|| last_snapshot.id()))
+    }
+
+    //Scan what is staged and the snapshot for secrets, then follow the secret scan policy
+    //Returns the trees to back up and the warning to show, None when the backup is blocked
+    fn scan_for_secrets(
+        &self,
+        head: &Commit,
+        index_tree_id: Oid,
+        tree_id: Oid,
+    ) -> Result<Option<(Oid, Oid, Option<String>)>, git2::Error> {
+        let config = &self.config;
+        if config.secret_scan_policy == SecretScanPolicy::Off {
+            return Ok(Some((index_tree_id, tree_id, None)));
+        }
+
+        let scanner = SecretScanner::new(&config.secret_rules);
+        let head_tree = head.tree()?;
+        let findings = scanner.scan_changes(
+            &self.repo,
+            &[
+                (Some(&head_tree), index_tree_id),
+                (Some(&head_tree), tree_id),
+            ],
+        )?;
+
+        if findings.is_empty() {
+            return Ok(Some((index_tree_id, tree_id, None)));
+        }
+
+        let report: Vec<String> = findings.iter().map(|finding| finding.to_string()).collect();
+        let report = report.join("\n");
+
+        match config.secret_scan_policy {
+            //Reported once, the repository is not retried until it changes again
+            SecretScanPolicy::Block => {
+                let message = format!(
+                    "The backup of {} is blocked, secrets are found in:\n{}",
+                    self.path, report
+                );
+                println!("{}", message);
+                notification_service::show_notification("Backup blocked".to_string(), message);
+                Ok(None)
+            }
+            SecretScanPolicy::Redact => {
+                let mut paths: Vec<&str> = findings
+                    .iter()
+                    .map(|finding| finding.path.as_str())
+                    .collect();
+                paths.sort();
+                paths.dedup();
+                let index_tree_id = git2_api_wrapper::revert_paths_in_tree(
+                    &self.repo,
+                    index_tree_id,
+                    &head_tree,
+                    &paths,
+                )?;
+                let tree_id = git2_api_wrapper::revert_paths_in_tree(
+                    &self.repo, tree_id, &head_tree, &paths,
+                )?;
+                Ok(Some((
+                    index_tree_id,
+                    tree_id,
+                    Some(format!(
+                        "The changes of these files are left out of the backup of {}:\n{}",
+                        self.path, report
+                    )),
+                )))
+            }
+            _ => Ok(Some((
+                index_tree_id,
+                tree_id,
+                Some(format!(
+                    "The backup of {} contains secrets:\n{}",
+                    self.path, report
+                )),
+            ))),
+        }
+    }
+
+    //The per-repository identity first, then the git config of the repository, then the CommitPal identity
+    fn get_signature(&self) -> Result<Signature<'static>, git2::Error> {
+        let config = &self.config;
+        let identity = config
+            .get_repository_settings(&self.path)
+            .and_then(|settings| settings.commit_identity.as_ref());
+        if let Some(identity) = identity {
+            return Signature::now(&identity.name, &identity.email);
+        }
+
+        match self.repo.signature() {
+            Ok(signature) => Ok(signature),
+            Err(_) => Signature::now(&config.commit_identity.name, &config.commit_identity.email),
+        }
+    }
+
+    //The tree of the working directory, following the snapshot policy and .commitpalignore
+    pub fn build_snapshot_tree(&self) -> Result<Oid, git2::Error> {
+        let config = &self.config;
+        let rules = SnapshotRules::new(
+            Path::new(&self.path),
+            config.get_snapshot_policy(&self.path),
+        );
+
+        git2_api_wrapper::build_snapshot_tree(
+            &self.repo,
+            rules.needs_ignored_files(),
+            &|path, status| rules.should_capture(path, status),
+            &|path| rules.should_capture_in_ignored_folder(path),
+        )
+    }
+
+    //Returns the name of the remote reference the snapshot is pushed to
+    fn push_snapshot(
+        &self,
+        snapshot_id: Oid,
+        source_branch: &str,
+        created_at: DateTime<Local>,
+    ) -> Result<String, git2::Error> {
+        let config = &self.config;
+        let mode = config.backup_branch_mode;
+        let backup_ref_name = backup_executor::get_back_up_ref_name(
+            source_branch,
+            mode,
+            &config.backup_ref_namespace,
+            config.get_branch_name_template(&self.path),
+            created_at,
+        );
+
+        //In rolling mode, the previous backup is the first parent so the backup history can be browsed
+        //The source branch HEAD is recorded as the second parent
+        let previous_backup = match mode {
+            BackupBranchMode::Timestamped => None,
+            BackupBranchMode::Rolling => {
+                git2_api_wrapper::fetch_remote_ref(&self.repo, &backup_ref_name, self.remote()?)?
+            }
+        };
+
+        let commit_id = if config.encrypt_backups {
+            let snapshot = self.repo.find_commit(snapshot_id)?;
+            let previous_backup = match previous_backup {
+                Some(previous_backup) => Some(self.repo.find_commit(previous_backup)?),
+                None => None,
+            };
+            backup_encryptor::encrypt_snapshot(
+                &self.repo,
+                &snapshot,
+                source_branch,
+                previous_backup.as_ref(),
+                config.obfuscate_paths,
+            )?
+        } else {
+            match previous_backup {
+                Some(previous_backup) => {
+                    let snapshot = self.repo.find_commit(snapshot_id)?;
+                    let previous_backup = self.repo.find_commit(previous_backup)?;
+                    let (head, index_commit) = git2_api_wrapper::get_backup_parts(&snapshot)?;
+                    let mut parents = vec![&previous_backup, &head];
+                    parents.extend(index_commit.as_ref());
+                    //The snapshot already carries the resolved identity
+                    let author = snapshot.author();
+                    git2_api_wrapper::commit_all_changes(
+                        &self.repo,
+                        snapshot.tree_id(),
+                        &parents,
+                        snapshot.message().unwrap_or_default(),
+                        &author,
+                    )?
+                }
+                None => snapshot_id,
+            }
+        };
+
+        git2_api_wrapper::push_to_remote(&self.repo, commit_id, &backup_ref_name, self.remote()?)?;
+
+        println!(
+            "Snapshot {} of {} is pushed to {}",
+            commit_id, self.path, backup_ref_name
+        );
+
+        Ok(backup_ref_name)
+    }
+
+    //The local snapshots that have not been pushed yet, oldest first
+    //Together with the pushed references, the reflogs act as a persistent outbox
+    pub fn get_pending_snapshots(&self) -> Result<Vec<PendingSnapshot>, git2::Error> {
+        let mut pending_snapshots = Vec::new();
+
+        for local_ref_name in self.get_local_back_up_ref_names()? {
+            let source_branch = match backup_executor::parse_local_back_up_ref_name(&local_ref_name)
+            {
+                Some(source_branch) => source_branch,
+                None => continue,
+            };
+            let pushed_ref_name = backup_executor::get_pushed_back_up_ref_name(source_branch);
+            let last_pushed = self.repo.refname_to_id(&pushed_ref_name).ok();
+
+            let history = git2_api_wrapper::get_reflog_history(&self.repo, &local_ref_name)?;
+            let pending: Vec<&(Oid, i64)> = match last_pushed {
+                Some(last_pushed) => match history.iter().rposition(|(oid, _)| *oid == last_pushed)
+                {
+                    Some(position) => history[position + 1..].iter().collect(),
+                    //The last pushed snapshot expired from the reflog, only the newer ones are pending
+                    None => {
+                        let pushed_at = self.repo.find_commit(last_pushed)?.time().seconds();
+                        history
+                            .iter()
+                            .filter(|(_, time)| *time > pushed_at)
+                            .collect()
+                    }
+                },
+                None => history.iter().collect(),
+            };
+
+            for (snapshot_id, time) in pending {
+                pending_snapshots.push(PendingSnapshot {
+                    source_branch: source_branch.to_string(),
+                    snapshot_id: *snapshot_id,
+                    created_at: match DateTime::from_timestamp(*time, 0) {
+                        Some(created_at) => created_at.with_timezone(&Local),
+                        None => Local::now(),
+                    },
+                });
+            }
+        }
+
+        pending_snapshots.sort_by_key(|pending_snapshot| pending_snapshot.created_at);
+        Ok(pending_snapshots)
+    }
+
+    //Push the pending snapshots, oldest first, and stop at the first failure
+    //Returns the number of pushed snapshots
+    pub fn push_pending_snapshots(&self) -> Result<usize, git2::Error> {
+        let pending_snapshots = self.get_pending_snapshots()?;
+
+        for pending_snapshot in &pending_snapshots {
+            let backup_ref_name = self.push_snapshot(
+                pending_snapshot.snapshot_id,
+                &pending_snapshot.source_branch,
+                pending_snapshot.created_at,
+            )?;
+            git2_api_wrapper::update_local_ref(
+                &self.repo,
+                &backup_executor::get_pushed_back_up_ref_name(&pending_snapshot.source_branch),
+                pending_snapshot.snapshot_id,
+                &format!("CommitPal push to {}", backup_ref_name),
+            )?;
+        }
+
+        Ok(pending_snapshots.len())
+    }
+
+    //Back up the local branches whose commits are not on their upstream, each one again when its tip moves
+    //Once a branch is pushed upstream or deleted, its backup is removed
+    //Encrypted backups only hold the files at the tip of the branch, not its history
+    //Returns the number of pushed branches
+    pub fn push_unpushed_commits(&self) -> Result<usize, git2::Error> {
+        let config = &self.config;
+        let unpushed_branches = git2_api_wrapper::get_unpushed_branches(&self.repo)?;
+        let mut count = 0;
+
+        for (branch, tip) in &unpushed_branches {
+            let pushed_ref_name = backup_executor::get_pushed_commits_ref_name(branch);
+            if self.repo.refname_to_id(&pushed_ref_name).ok() == Some(*tip) {
+                continue;
+            }
+
+            if !self.check_unpushed_commits_for_secrets(branch, *tip, &pushed_ref_name)? {
+                continue;
+            }
+
+            //The history cannot be encrypted, so only the files at the tip of the branch are backed up
+            let commit_id = if config.encrypt_backups {
+                let tip_commit = self.repo.find_commit(*tip)?;
+                backup_encryptor::encrypt_commit(
+                    &self.repo,
+                    &tip_commit,
+                    branch,
+                    *tip,
+                    None,
+                    config.obfuscate_paths,
+                )?
+            } else {
+                *tip
+            };
+
+            let backup_ref_name =
+                backup_executor::get_commit_back_up_ref_name(branch, &config.backup_ref_namespace);
+            git2_api_wrapper::force_push_to_remote(
+                &self.repo,
+                commit_id,
+                &backup_ref_name,
+                self.remote()?,
+            )?;
+            git2_api_wrapper::update_local_ref(
+                &self.repo,
+                &pushed_ref_name,
+                *tip,
+                &format!("CommitPal push to {}", backup_ref_name),
+            )?;
+            println!(
+                "Unpushed commits of {} in {} are pushed to {}",
+                branch, self.path, backup_ref_name
+            );
+            count += 1;
+        }
+
+        for pushed_ref_name in self.get_pushed_commits_ref_names()? {
+            let branch = match backup_executor::parse_pushed_commits_ref_name(&pushed_ref_name) {
+                Some(branch) => branch,
+                None => continue,
+            };
+            if unpushed_branches.iter().any(|(name, _)| name == branch) {
+                continue;
+            }
+
+            let backup_ref_name =
+                backup_executor::get_commit_back_up_ref_name(branch, &config.backup_ref_namespace);
+            git2_api_wrapper::delete_remote_refs(&self.repo, &[&backup_ref_name], self.remote()?)?;
+            self.repo.find_reference(&pushed_ref_name)?.delete()?;
+            println!(
+                "{} in {} has no unpushed commits anymore, {} is deleted",
+                branch, self.path, backup_ref_name
+            );
+        }
+
+        Ok(count)
+    }
+
+    //Back up every stash entry once, along with its index, worktree and untracked parents
+    //The backups are kept after the entries are dropped, only the local records are removed
+    //Returns the number of pushed stash entries
+    pub fn push_stashes(&self) -> Result<usize, git2::Error> {
+        let config = &self.config;
+
+        //The reflog of refs/stash holds every entry, oldest first
+        let stash_ids: Vec<Oid> = git2_api_wrapper::get_reflog_history(&self.repo, STASH_REF_NAME)?
+            .into_iter()
+            .map(|(stash_id, _)| stash_id)
+            .collect();
+        let mut count = 0;
+
+        for stash_id in &stash_ids {
+            let pushed_ref_name = backup_executor::get_pushed_stash_ref_name(*stash_id);
+            if self.repo.find_reference(&pushed_ref_name).is_ok() {
+                continue;
+            }
+
+            let stash = self.repo.find_commit(*stash_id)?;
+            if !self.check_stash_for_secrets(&stash)? {
+                continue;
+            }
+
+            //Only the worktree of an encrypted entry is backed up, without its index and untracked files
+            let commit_id = if config.encrypt_backups {
+                backup_encryptor::encrypt_commit(
+                    &self.repo,
+                    &stash,
+                    STASH_SOURCE_BRANCH,
+                    stash.parent_id(0)?,
+                    None,
+                    config.obfuscate_paths,
+                )?
+            } else {
+                *stash_id
+            };
+
+            let backup_ref_name = backup_executor::get_stash_back_up_ref_name(
+                *stash_id,
+                &config.backup_ref_namespace,
+            );
+            git2_api_wrapper::push_to_remote(
+                &self.repo,
+                commit_id,
+                &backup_ref_name,
+                self.remote()?,
+            )?;
+            git2_api_wrapper::update_local_ref(
+                &self.repo,
+                &pushed_ref_name,
+                *stash_id,
+                &format!("CommitPal push to {}", backup_ref_name),
+            )?;
+            println!(
+                "Stash entry {} of {} is pushed to {}",
+                stash_id, self.path, backup_ref_name
+            );
+            count += 1;
+        }
+
+        for reference in self.repo.references()? {
+            let mut reference = reference?;
+            let stash_id = match reference
+                .name()
+                .and_then(backup_executor::parse_pushed_stash_ref_name)
+            {
+                Some(stash_id) => stash_id,
+                None => continue,
+            };
+            if !stash_ids.contains(&stash_id) {
+                reference.delete()?;
+            }
+        }
+
+        Ok(count)
+    }
+
+    //Scan the commits of the branch that are neither upstream nor backed up yet
+    //Returns whether the branch can be pushed
+    fn check_unpushed_commits_for_secrets(
+        &self,
+        branch: &str,
+        tip: Oid,
+        pushed_ref_name: &str,
+    ) -> Result<bool, git2::Error> {
+        if self.config.secret_scan_policy == SecretScanPolicy::Off {
+            return Ok(true);
+        }
+
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push(tip)?;
+        revwalk.hide_glob("refs/remotes/*")?;
+        if let Ok(pushed_id) = self.repo.refname_to_id(pushed_ref_name) {
+            revwalk.hide(pushed_id)?;
+        }
+
+        //Each commit is compared with its first parent, a root commit with nothing
+        let mut changes = Vec::new();
+        for commit_id in revwalk {
+            let commit = self.repo.find_commit(commit_id?)?;
+            let base_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            changes.push((base_tree, commit.tree_id()));
+        }
+
+        self.check_history_for_secrets(
+            &format!("The unpushed commits of {} in {}", branch, self.path),
+            &changes,
+        )
+    }
+
+    //Scan the worktree, index and untracked files of a stash entry against the commit it was made on
+    //Returns whether the entry can be pushed
+    fn check_stash_for_secrets(&self, stash: &Commit) -> Result<bool, git2::Error> {
+        if self.config.secret_scan_policy == SecretScanPolicy::Off {
+            return Ok(true);
+        }
+
+        let base_tree = stash.parent(0)?.tree()?;
+        let mut changes = vec![
+            (Some(base_tree.clone()), stash.tree_id()),
+            (Some(base_tree), stash.parent(1)?.tree_id()),
+        ];
+        //The untracked files are in a commit without a parent
+        if let Ok(untracked) = stash.parent(2) {
+            changes.push((None, untracked.tree_id()));
+        }
+
+        self.check_history_for_secrets(
+            &format!("The files of stash entry {} of {}", stash.id(), self.path),
+            &changes,
+        )
+    }
+
+    //Commits are pushed as they are, so their secrets cannot be redacted and they are blocked instead
+    fn check_history_for_secrets(
+        &self,
+        description: &str,
+        changes: &[(Option<Tree>, Oid)],
+    ) -> Result<bool, git2::Error> {
+        let scanner = SecretScanner::new(&self.config.secret_rules);
+        let changes: Vec<(Option<&Tree>, Oid)> = changes
+            .iter()
+            .map(|(base_tree, tree_id)| (base_tree.as_ref(), *tree_id))
+            .collect();
+        let findings = scanner.scan_changes(&self.repo, &changes)?;
+        if findings.is_empty() {
+            return Ok(true);
+        }
+
+        let report: Vec<String> = findings.iter().map(|finding| finding.to_string()).collect();
+        let report = report.join("\n");
+
+        if self.config.secret_scan_policy == SecretScanPolicy::Warn {
+            let message = format!("{} contain secrets:\n{}", description, report);
+            println!("{}", message);
+            notification_service::show_notification(
+                "Secrets found in the backup".to_string(),
+                message,
+            );
+            return Ok(true);
+        }
+
+        let message = format!(
+            "{} are not backed up, secrets are found in:\n{}",
+            description, report
+        );
+        println!("{}", message);
+        notification_service::show_notification("Backup blocked".to_string(), message);
+        Ok(false)
+    }
+
+    //The backed up stash entries of every host
+    pub fn list_stash_backups(&self) -> Result<Vec<(String, Oid)>, git2::Error> {
+        let namespace = &self.config.backup_ref_namespace;
+        git2_api_wrapper::list_remote_refs(
+            &self.repo,
+            &backup_executor::get_stash_back_up_prefix(namespace),
+            self.remote()?,
+        )
+    }
+
+    fn get_pushed_commits_ref_names(&self) -> Result<Vec<String>, git2::Error> {
+        let mut ref_names = Vec::new();
+        for reference in self.repo.references()? {
+            if let Some(name) = reference?.name() {
+                if backup_executor::parse_pushed_commits_ref_name(name).is_some() {
+                    ref_names.push(name.to_string());
+                }
+            }
+        }
+        Ok(ref_names)
+    }
+
+    //The backed up branches of every host, with the commits they point to
+    pub fn list_commit_backups(&self) -> Result<Vec<(String, Oid)>, git2::Error> {
+        let namespace = &self.config.backup_ref_namespace;
+        git2_api_wrapper::list_remote_refs(
+            &self.repo,
+            &backup_executor::get_commit_back_up_prefix(namespace),
+            self.remote()?,
+        )
+    }
+
+    //Failed pushes are retried per remote host, so one unreachable server does not delay the others
+    pub fn get_remote_host(&self) -> String {
+        let remote = match self.remote() {
+            Ok(remote) => remote,
+            //The push fails on its own, without holding back the repositories that have a remote
+            Err(_) => return self.path.clone(),
+        };
+
+        match git2_api_wrapper::get_remote_url(&self.repo, remote) {
+            Ok(url) => git2_api_wrapper::get_host_from_url(&url),
+            Err(_) => remote.target.to_string(),
+        }
+    }
+
+    //The snapshots stored in this repository, available without any network access
+    pub fn list_local_backups(&self) -> Result<Vec<BackupRef>, git2::Error> {
+        let host = backup_executor::get_host_name();
+        let mut backups = Vec::new();
+
+        for local_ref_name in self.get_local_back_up_ref_names()? {
+            let source_branch = match backup_executor::parse_local_back_up_ref_name(&local_ref_name)
+            {
+                Some(source_branch) => source_branch.to_string(),
+                None => continue,
+            };
+
+            let history = git2_api_wrapper::get_reflog_history(&self.repo, &local_ref_name)?;
+            let count = history.len();
+            for (i, (commit_id, time)) in history.into_iter().enumerate() {
+                backups.push(BackupRef {
+                    //Same as how git names reflog entries, the latest one is @{0}
+                    ref_name: format!("{}@{{{}}}", local_ref_name, count - 1 - i),
+                    host: host.clone(),
+                    source_branch: source_branch.clone(),
+                    created_at: DateTime::from_timestamp(time, 0)
+                        .map(|time| time.with_timezone(&Local).naive_local()),
+                    commit_id,
+                });
+            }
+        }
+
+        Ok(backups)
+    }
+
+    fn get_local_back_up_ref_names(&self) -> Result<Vec<String>, git2::Error> {
+        let mut ref_names = Vec::new();
+        for reference in self.repo.references()? {
+            if let Some(name) = reference?.name() {
+                if backup_executor::parse_local_back_up_ref_name(name).is_some() {
+                    ref_names.push(name.to_string());
+                }
+            }
+        }
+        Ok(ref_names)
+    }
+
+    //All the backups of this repository in the configured namespace, from every host, of one source branch if it is given
+    //The names are parsed with the templates first, only the references none of them matches are fetched to read their trailers
+    //The trailers of a commit that is already available locally tell its host and branch whatever template named it
+    pub fn list_backups(&self, source_branch: Option<&str>) -> Result<Vec<BackupRef>, git2::Error> {
+        let config = &self.config;
+        let namespace = &config.backup_ref_namespace;
+        let prefix = format!("{}/", namespace.trim_end_matches('/'));
+
+        let mut templates = vec![
+            config.get_branch_name_template(&self.path),
+            config.branch_name_template.as_str(),
+            DEFAULT_BRANCH_NAME_TEMPLATE,
+        ];
+        templates.dedup();
+        let mut ref_regexes = Vec::new();
+        for template in templates {
+            ref_regexes.push(backup_executor::get_back_up_ref_regex(namespace, template)?);
+        }
+
+        let mut backups = Vec::new();
+        let mut unknown_refs = Vec::new();
+        for (ref_name, oid) in
+            git2_api_wrapper::list_remote_refs(&self.repo, &prefix, self.remote()?)?
+        {
+            let commit = self.repo.find_commit(oid).ok();
+            let backup = commit
+                .as_ref()
+                .and_then(|commit| {
+                    backup_executor::parse_back_up_commit(
+                        &ref_name,
+                        commit.message().unwrap_or_default(),
+                        oid,
+                    )
+                })
+                .or_else(|| {
+                    ref_regexes.iter().find_map(|ref_regex| {
+                        backup_executor::parse_back_up_ref_name(&ref_name, ref_regex, oid)
+                    })
+                });
+
+            //Every template contains {branch}, so a reference without the branch in its name is of another branch
+            let is_other_branch =
+                source_branch.is_some_and(|source_branch| !ref_name.contains(source_branch));
+            match backup {
+                Some(backup) => backups.push(backup),
+                None if commit.is_none() && !is_other_branch => unknown_refs.push((ref_name, oid)),
+                None => {}
+            }
+        }
+
+        if !unknown_refs.is_empty() {
+            let ref_names: Vec<&str> = unknown_refs
+                .iter()
+                .map(|(ref_name, _)| ref_name.as_str())
+                .collect();
+            git2_api_wrapper::fetch_remote_refs(&self.repo, &ref_names, self.remote()?)?;
+
+            for (ref_name, oid) in &unknown_refs {
+                if let Ok(commit) = self.repo.find_commit(*oid) {
+                    backups.extend(backup_executor::parse_back_up_commit(
+                        ref_name,
+                        commit.message().unwrap_or_default(),
+                        *oid,
+                    ));
+                }
+            }
+        }
+
+        if let Some(source_branch) = source_branch {
+            backups.retain(|backup| backup.source_branch == source_branch);
+        }
+
+        backups.sort_by(|a, b| {
+            (&a.host, &a.source_branch, a.created_at).cmp(&(
+                &b.host,
+                &b.source_branch,
+                b.created_at,
+            ))
+        });
+
+        Ok(backups)
+    }
+}
+
+//A backup url of the repository, then its configured remote, then the global backup url
+//Otherwise the upstream remote of the current branch, origin, or the only remote if there is just one
+fn resolve_backup_remote(
+    repo: &Repository,
+    repo_path: &str,
+    config: &Config,
+) -> Result<RemoteTarget, String> {
+    if let Some(settings) = config.get_repository_settings(repo_path) {
+        if let Some(url) = &settings.backup_url {
+            return Ok(resolve_backup_url(url, repo_path));
+        }
+
+        if let Some(remote_name) = &settings.backup_remote {
+            return Ok(RemoteTarget::Named(remote_name.clone()));
+        }
+    }
+
+    if let Some(url) = &config.backup_url {
+        let repo_name = Path::new(repo_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        return Ok(resolve_backup_url(
+            &url.replace(BACKUP_URL_REPO_PLACEHOLDER, &repo_name),
+            repo_path,
+        ));
+    }
+
+    let upstream_remote = repo
+        .head()
+        .ok()
+        .and_then(|head| head.name().map(|name| name.to_string()))
+        .and_then(|head_name| repo.branch_upstream_remote(&head_name).ok())
+        .and_then(|remote_name| remote_name.as_str().map(|name| name.to_string()));
+    if let Some(remote_name) = upstream_remote {
+        return Ok(RemoteTarget::Named(remote_name));
+    }
+
+    let remotes = match repo.remotes() {
+        Ok(remotes) => remotes,
+        Err(e) => return Err(format!("failed to get remotes: {}", e)),
+    };
+    let remote_names: Vec<&str> = remotes.iter().flatten().collect();
+
+    match remote_names.as_slice() {
+        [] => Err("The repository has no remote".to_string()),
+        [only_remote] => Ok(RemoteTarget::Named(only_remote.to_string())),
+        _ if remote_names.contains(&"origin") => Ok(RemoteTarget::Named("origin".to_string())),
+        _ => Err(format!(
+            "Cannot choose between the remotes {}, please set one with the set-remote command",
+            remote_names.join(", ")
+        )),
+    }
+}
+
+//Relative paths are relative to the repository, wherever the command is run from
+fn resolve_backup_url(url: &str, repo_path: &str) -> RemoteTarget {
+    match git2_api_wrapper::get_local_path_from_url(url) {
+        Some(path) if path.is_relative() => {
+            let path = Path::new(repo_path).join(path);
+            //The backup repository may not exist yet, its parent folder usually does
+            let absolute_path = match (path.parent(), path.file_name()) {
+                (Some(parent), Some(name)) if !path.exists() => {
+                    let parent = file_system::get_absolute_path(&parent.to_string_lossy());
+                    Path::new(&parent).join(name).to_string_lossy().to_string()
+                }
+                _ => file_system::get_absolute_path(&path.to_string_lossy()),
+            };
+            RemoteTarget::Url(absolute_path)
+        }
+        _ => RemoteTarget::Url(url.to_string()),
+    }
+}
+
+fn get_auth_type(repo: &Repository, target: &RemoteTarget) -> Result<AuthType, String> {
+    let remote_name = match target {
+        RemoteTarget::Named(remote_name) => remote_name,
+        RemoteTarget::Url(url) => return git2_api_wrapper::get_auth_type_from_url(url),
+    };
+
+    let remote = match repo.find_remote(remote_name) {
+        Ok(remote) => remote,
+        Err(e) => return Err(format!("failed to find remote {}: {}", remote_name, e)),
+    };
+
+    match remote.pushurl().or(remote.url()) {
+        Some(url) => git2_api_wrapper::get_auth_type_from_url(url),
+        None => Err(format!("remote {} has no url", remote_name)),
+    }
+}
@@ -0,0 +1,179 @@
+use git2::Status;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::fs;
+use std::path::Path;
+
+use crate::data_structures::config::SnapshotPolicy;
+
+const SNAPSHOT_RULES_FILE_NAME: &str = ".commitpalignore";
+
+//Decides which paths of the working tree go into a snapshot
+//The policy sets the default, the rules of .commitpalignore override it
+pub struct SnapshotRules {
+    policy: SnapshotPolicy,
+    //Same syntax as .gitignore, a rule starting with ! includes the path, the last matching rule wins
+    rules: Gitignore,
+    //The include rules with a folder in them, e.g. build/keep.txt for !/build/keep.txt
+    anchored_includes: Vec<String>,
+}
+
+impl SnapshotRules {
+    pub fn new(repo_path: &Path, policy: SnapshotPolicy) -> SnapshotRules {
+        let (rules, anchored_includes) = extract_rules_from_file(repo_path);
+        SnapshotRules {
+            policy,
+            rules,
+            anchored_includes,
+        }
+    }
+
+    //Ignored files only have to be listed when some of them may be captured
+    pub fn needs_ignored_files(&self) -> bool {
+        self.policy == SnapshotPolicy::Everything || self.rules.num_whitelists() > 0
+    }
+
+    //Excluded tracked files keep the content of HEAD
+    pub fn should_capture(&self, path: &str, status: Status) -> bool {
+        if let Some(is_include) = self.match_rules(path) {
+            return is_include;
+        }
+
+        if status.is_ignored() {
+            self.policy == SnapshotPolicy::Everything
+        } else if status.is_wt_new() {
+            self.policy != SnapshotPolicy::TrackedOnly
+        } else {
+            true
+        }
+    }
+
+    //Ignored folders such as target or node_modules are left out even with the Everything policy
+    //A folder is only walked into when a rule includes it or a path in it, then only the included files are captured
+    pub fn should_capture_in_ignored_folder(&self, path: &str) -> bool {
+        let folder = match path.strip_suffix('/') {
+            Some(folder) => folder,
+            None => return self.match_rules(path) == Some(true),
+        };
+
+        self.rules.matched(folder, true).is_whitelist()
+            || self
+                .anchored_includes
+                .iter()
+                .any(|include| include.starts_with(&format!("{}/", folder)))
+    }
+
+    fn match_rules(&self, path: &str) -> Option<bool> {
+        let matched = self.rules.matched_path_or_any_parents(path, false);
+        if matched.is_whitelist() {
+            Some(true)
+        } else if matched.is_ignore() {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+fn extract_rules_from_file(repo_path: &Path) -> (Gitignore, Vec<String>) {
+    let path = repo_path.join(SNAPSHOT_RULES_FILE_NAME);
+    let mut builder = GitignoreBuilder::new(repo_path);
+    let mut anchored_includes = Vec::new();
+
+    if let Ok(content) = fs::read_to_string(&path) {
+        for line in content.lines() {
+            if let Err(e) = builder.add_line(Some(path.clone()), line) {
+                println!("Invalid rule {} in {}: {}", line, path.display(), e);
+                continue;
+            }
+
+            if let Some(pattern) = line.trim().strip_prefix('!') {
+                let pattern = pattern.trim_start_matches('/');
+                if pattern.trim_end_matches('/').contains('/') {
+                    anchored_includes.push(pattern.to_string());
+                }
+            }
+        }
+    }
+
+    match builder.build() {
+        Ok(rules) => (rules, anchored_includes),
+        Err(e) => {
+            println!("Invalid rules in {}: {}", path.display(), e);
+            (Gitignore::empty(), Vec::new())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(content: &str, policy: SnapshotPolicy) -> (tempfile::TempDir, SnapshotRules) {
+        let folder = tempfile::tempdir().unwrap();
+        fs::write(folder.path().join(SNAPSHOT_RULES_FILE_NAME), content).unwrap();
+        let rules = SnapshotRules::new(folder.path(), policy);
+        (folder, rules)
+    }
+
+    #[test]
+    fn policy_decides_without_rules() {
+        let (_folder, tracked_only) = rules("", SnapshotPolicy::TrackedOnly);
+        assert!(tracked_only.should_capture("src/main.rs", Status::WT_MODIFIED));
+        assert!(!tracked_only.should_capture("notes.txt", Status::WT_NEW));
+        assert!(!tracked_only.needs_ignored_files());
+
+        let (_folder, untracked) = rules("", SnapshotPolicy::Untracked);
+        assert!(untracked.should_capture("notes.txt", Status::WT_NEW));
+        assert!(!untracked.should_capture(".env", Status::IGNORED));
+
+        let (_folder, everything) = rules("", SnapshotPolicy::Everything);
+        assert!(everything.should_capture(".env", Status::IGNORED));
+        assert!(everything.needs_ignored_files());
+    }
+
+    #[test]
+    fn rules_follow_gitignore_syntax() {
+        let (_folder, rules) = rules(
+            "# comment\n*.log\n/build\ndocs/\n!important.log\n",
+            SnapshotPolicy::Untracked,
+        );
+
+        //A pattern without a slash matches at any depth
+        assert!(!rules.should_capture("debug.log", Status::WT_NEW));
+        assert!(!rules.should_capture("src/nested/debug.log", Status::WT_NEW));
+        //A leading slash anchors the pattern to the root of the repository
+        assert!(!rules.should_capture("build/output.bin", Status::WT_NEW));
+        assert!(rules.should_capture("src/build/output.bin", Status::WT_NEW));
+        //A trailing slash only matches folders, at any depth
+        assert!(!rules.should_capture("src/docs/index.md", Status::WT_NEW));
+        //The last matching rule wins
+        assert!(rules.should_capture("important.log", Status::WT_NEW));
+        assert!(rules.should_capture("src/main.rs", Status::WT_MODIFIED));
+    }
+
+    #[test]
+    fn include_rules_capture_ignored_files() {
+        let (_folder, rules) = rules("!.env\n", SnapshotPolicy::Untracked);
+
+        assert!(rules.needs_ignored_files());
+        assert!(rules.should_capture(".env", Status::IGNORED));
+        assert!(rules.should_capture("config/.env", Status::IGNORED));
+        assert!(!rules.should_capture("secret.key", Status::IGNORED));
+    }
+
+    #[test]
+    fn ignored_folders_are_only_walked_into_when_included() {
+        let (_folder, rules) = rules("!vendor/\n!/build/keep.txt\n", SnapshotPolicy::Everything);
+
+        assert!(!rules.should_capture_in_ignored_folder("target/"));
+        assert!(!rules.should_capture_in_ignored_folder("node_modules/"));
+        assert!(!rules.should_capture_in_ignored_folder("build-cache/"));
+
+        assert!(rules.should_capture_in_ignored_folder("vendor/"));
+        assert!(rules.should_capture_in_ignored_folder("vendor/lib/code.c"));
+
+        assert!(rules.should_capture_in_ignored_folder("build/"));
+        assert!(rules.should_capture_in_ignored_folder("build/keep.txt"));
+        assert!(!rules.should_capture_in_ignored_folder("build/output.bin"));
+    }
+}
@@ -29,17 +29,17 @@ Please follow the prompts to set up your environment.\n
     config_manager::reset();
 
     let pat_result = set_personal_access_token();
-    if pat_result.is_err() {
-        return;
-    } else if pat_result.unwrap() {
-        auth_count += 1;
+    match pat_result {
+        Err(_) => return,
+        Ok(true) => auth_count += 1,
+        Ok(false) => {}
     }
 
     let ssh_result = set_ssh_path();
-    if ssh_result.is_err() {
-        return;
-    } else if ssh_result.unwrap() {
-        auth_count += 1;
+    match ssh_result {
+        Err(_) => return,
+        Ok(true) => auth_count += 1,
+        Ok(false) => {}
     }
 
     let user_input_backup_frequency = user_input_handler(
@@ -55,7 +55,7 @@ Please follow the prompts to set up your environment.\n
             }
         };
 
-        config_manager::set_backup_frequency(backup_frequency);
+        config_manager::set_backup_frequency(None, Some(backup_frequency));
     }
 
     if auth_count < 1 {
@@ -122,7 +122,7 @@ fn set_ssh_path() -> Result<bool, ()> {
     }
 }
 
-fn user_input_handler(message: &str) -> String {
+pub fn user_input_handler(message: &str) -> String {
     println!("{}", message);
     io::stdout().flush().unwrap();
     let mut input = String::new();
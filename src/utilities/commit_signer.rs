@@ -0,0 +1,493 @@
+use git2::{Oid, Repository, Signature};
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tempfile::NamedTempFile;
+
+//Follows gpg.format of the repository, openpgp when it is not set
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SigningFormat {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+//The values of gpg.minTrustLevel, from the least to the most trusted
+const TRUST_LEVELS: [&str; 5] = ["undefined", "never", "marginal", "fully", "ultimate"];
+//marginal, a key nobody vouched for cannot vouch for a backup
+const DEFAULT_MIN_TRUST_LEVEL: usize = 2;
+
+pub struct SigningConfig {
+    pub format: SigningFormat,
+    pub program: String,
+    pub key: String,
+}
+
+pub enum SignatureStatus {
+    Good(String),
+    Bad(String),
+    //A valid signature of a key that is trusted less than gpg.minTrustLevel
+    Untrusted(String),
+    Unsigned,
+    //The signature could not be checked, e.g. the public key is unknown
+    Unverifiable(String),
+}
+
+impl SignatureStatus {
+    pub fn is_good(&self) -> bool {
+        matches!(self, SignatureStatus::Good(_))
+    }
+}
+
+impl fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SignatureStatus::Good(signer) => write!(f, "good signature from {}", signer),
+            SignatureStatus::Bad(reason) => write!(f, "BAD signature: {}", reason),
+            SignatureStatus::Untrusted(signer) => {
+                write!(f, "signature from {} whose key is not trusted", signer)
+            }
+            SignatureStatus::Unsigned => write!(f, "unsigned"),
+            SignatureStatus::Unverifiable(reason) => write!(f, "unverifiable: {}", reason),
+        }
+    }
+}
+
+//None when commit.gpgsign is not enabled in the repository
+//Without user.signingkey, gpg picks the key of the committer the same way git does
+pub fn get_signing_config(
+    repo: &Repository,
+    committer: &Signature,
+) -> Result<Option<SigningConfig>, git2::Error> {
+    let config = repo.config()?;
+    if !config.get_bool("commit.gpgsign").unwrap_or(false) {
+        return Ok(None);
+    }
+
+    let format = match config.get_string("gpg.format") {
+        Ok(format) => match format.as_str() {
+            "openpgp" => SigningFormat::OpenPgp,
+            "x509" => SigningFormat::X509,
+            "ssh" => SigningFormat::Ssh,
+            _ => {
+                return Err(git2::Error::from_str(&format!(
+                    "Unsupported gpg.format {}",
+                    format
+                )))
+            }
+        },
+        Err(_) => SigningFormat::OpenPgp,
+    };
+
+    let key = match config.get_string("user.signingkey") {
+        Ok(key) => key,
+        Err(_) if format == SigningFormat::Ssh => {
+            return Err(git2::Error::from_str(
+                "commit.gpgsign is set but user.signingkey is missing for ssh signing",
+            ));
+        }
+        Err(_) => format!(
+            "{} <{}>",
+            committer.name().unwrap_or_default(),
+            committer.email().unwrap_or_default()
+        ),
+    };
+
+    Ok(Some(SigningConfig {
+        format,
+        program: get_program(&config, format),
+        key,
+    }))
+}
+
+fn get_program(config: &git2::Config, format: SigningFormat) -> String {
+    let (key, default) = match format {
+        SigningFormat::OpenPgp => ("gpg.openpgp.program", "gpg"),
+        SigningFormat::X509 => ("gpg.x509.program", "gpgsm"),
+        SigningFormat::Ssh => ("gpg.ssh.program", "ssh-keygen"),
+    };
+
+    match config.get_string(key) {
+        Ok(program) => program,
+        //gpg.program is the older name of gpg.openpgp.program
+        Err(_) if format == SigningFormat::OpenPgp => config
+            .get_string("gpg.program")
+            .unwrap_or(default.to_string()),
+        Err(_) => default.to_string(),
+    }
+}
+
+//Returns the armored signature of the commit buffer
+pub fn sign(signing: &SigningConfig, buffer: &str) -> Result<String, git2::Error> {
+    let result = match signing.format {
+        SigningFormat::OpenPgp | SigningFormat::X509 => sign_with_gpg(signing, buffer),
+        SigningFormat::Ssh => sign_with_ssh(signing, buffer),
+    };
+
+    match result {
+        Ok(signature) => Ok(signature),
+        Err(e) => Err(git2::Error::from_str(&format!(
+            "Failed to sign the backup with {}: {}",
+            signing.program, e
+        ))),
+    }
+}
+
+fn sign_with_gpg(signing: &SigningConfig, buffer: &str) -> Result<String, String> {
+    let output = run(
+        Command::new(&signing.program).args(["--status-fd=2", "-bsau", &signing.key]),
+        buffer,
+    )?;
+
+    //gpg can exit successfully without signing, e.g. when the pinentry is cancelled
+    let status = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() || !status.contains("[GNUPG:] SIG_CREATED ") {
+        return Err(status.trim().to_string());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn sign_with_ssh(signing: &SigningConfig, buffer: &str) -> Result<String, String> {
+    //A literal public key is written to a file, the private key is then taken from the ssh agent
+    let literal_key = signing
+        .key
+        .strip_prefix("key::")
+        .or(Some(signing.key.as_str()).filter(|key| key.starts_with("ssh-")));
+
+    let key_file = match literal_key {
+        Some(literal_key) => Some(write_temp_file(literal_key)?),
+        None => None,
+    };
+
+    let mut command = Command::new(&signing.program);
+    command.args(["-Y", "sign", "-n", "git", "-f"]);
+    match &key_file {
+        Some(key_file) => {
+            command.arg(key_file.path()).arg("-U");
+        }
+        None => {
+            command.arg(expand_home(&signing.key));
+        }
+    }
+
+    //Without a file to sign, ssh-keygen signs stdin and writes the signature to stdout
+    let output = run(&mut command, buffer)?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn verify_commit(repo: &Repository, commit_id: Oid) -> SignatureStatus {
+    let (signature, signed_data) = match repo.extract_signature(&commit_id, None) {
+        Ok(extracted) => extracted,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return SignatureStatus::Unsigned,
+        Err(e) => return SignatureStatus::Unverifiable(e.message().to_string()),
+    };
+    let signature = signature.as_str().unwrap_or_default();
+    let signed_data = signed_data.as_str().unwrap_or_default();
+
+    let config = match repo.config() {
+        Ok(config) => config,
+        Err(e) => return SignatureStatus::Unverifiable(e.message().to_string()),
+    };
+
+    let min_trust_level = match config.get_string("gpg.minTrustLevel") {
+        Ok(level) => match TRUST_LEVELS.iter().position(|known| *known == level) {
+            Some(position) => position,
+            None => {
+                return SignatureStatus::Unverifiable(format!(
+                    "unknown gpg.minTrustLevel {}",
+                    level
+                ))
+            }
+        },
+        Err(_) => DEFAULT_MIN_TRUST_LEVEL,
+    };
+
+    let result = if signature.starts_with("-----BEGIN SSH SIGNATURE") {
+        verify_with_ssh(&config, signature, signed_data)
+    } else if signature.starts_with("-----BEGIN SIGNED MESSAGE") {
+        verify_with_gpg(
+            &get_program(&config, SigningFormat::X509),
+            signature,
+            signed_data,
+            min_trust_level,
+        )
+    } else {
+        verify_with_gpg(
+            &get_program(&config, SigningFormat::OpenPgp),
+            signature,
+            signed_data,
+            min_trust_level,
+        )
+    };
+
+    match result {
+        Ok(status) => status,
+        Err(e) => SignatureStatus::Unverifiable(e),
+    }
+}
+
+fn verify_with_gpg(
+    program: &str,
+    signature: &str,
+    signed_data: &str,
+    min_trust_level: usize,
+) -> Result<SignatureStatus, String> {
+    let signature_file = write_temp_file(signature)?;
+    let output = run(
+        Command::new(program)
+            .args(["--status-fd=1", "--verify"])
+            .arg(signature_file.path())
+            .arg("-"),
+        signed_data,
+    )?;
+
+    let status = String::from_utf8_lossy(&output.stdout);
+    match read_gpg_status(&status, min_trust_level) {
+        Some(status) => Ok(status),
+        None => Ok(SignatureStatus::Unverifiable(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        )),
+    }
+}
+
+//A good signature needs GOODSIG and VALIDSIG, and a key trusted at least as much as gpg.minTrustLevel
+//None when the status says nothing about the signature
+fn read_gpg_status(status: &str, min_trust_level: usize) -> Option<SignatureStatus> {
+    let mut signer = None;
+    let mut is_valid = false;
+    let mut trust_level = None;
+
+    for line in status.lines() {
+        let line = match line.strip_prefix("[GNUPG:] ") {
+            Some(line) => line,
+            None => continue,
+        };
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+
+        match keyword {
+            "GOODSIG" => {
+                //The key id is followed by the user id
+                signer = Some(
+                    rest.split_once(' ')
+                        .map_or(rest, |(_, uid)| uid)
+                        .to_string(),
+                );
+            }
+            "VALIDSIG" => is_valid = true,
+            "BADSIG" => return Some(SignatureStatus::Bad(rest.to_string())),
+            "EXPKEYSIG" => {
+                return Some(SignatureStatus::Bad(format!(
+                    "the key is expired: {}",
+                    rest
+                )))
+            }
+            "REVKEYSIG" => {
+                return Some(SignatureStatus::Bad(format!(
+                    "the key is revoked: {}",
+                    rest
+                )))
+            }
+            "NO_PUBKEY" => {
+                return Some(SignatureStatus::Unverifiable(
+                    "the public key is not in the keyring".to_string(),
+                ))
+            }
+            _ => {
+                if let Some(level) = keyword.strip_prefix("TRUST_") {
+                    trust_level = TRUST_LEVELS
+                        .iter()
+                        .position(|known| known.eq_ignore_ascii_case(level));
+                }
+            }
+        }
+    }
+
+    let signer = signer?;
+    if !is_valid {
+        return Some(SignatureStatus::Unverifiable(format!(
+            "the signature of {} is not valid",
+            signer
+        )));
+    }
+
+    match trust_level {
+        Some(trust_level) if trust_level >= min_trust_level => Some(SignatureStatus::Good(signer)),
+        _ => Some(SignatureStatus::Untrusted(signer)),
+    }
+}
+
+//Needs gpg.ssh.allowedSignersFile, the same as git verify-commit
+fn verify_with_ssh(
+    config: &git2::Config,
+    signature: &str,
+    signed_data: &str,
+) -> Result<SignatureStatus, String> {
+    let allowed_signers = match config.get_path("gpg.ssh.allowedSignersFile") {
+        Ok(path) => path,
+        Err(_) => {
+            return Ok(SignatureStatus::Unverifiable(
+                "gpg.ssh.allowedSignersFile is not configured".to_string(),
+            ))
+        }
+    };
+    let program = get_program(config, SigningFormat::Ssh);
+    let signature_file = write_temp_file(signature)?;
+
+    let output = run(
+        Command::new(&program)
+            .args(["-Y", "find-principals", "-f"])
+            .arg(&allowed_signers)
+            .arg("-s")
+            .arg(signature_file.path()),
+        "",
+    )?;
+    let principals = String::from_utf8_lossy(&output.stdout).to_string();
+    let principal = match principals.lines().next() {
+        Some(principal) if output.status.success() => principal,
+        _ => {
+            return Ok(SignatureStatus::Unverifiable(
+                "the key is not in the allowed signers file".to_string(),
+            ))
+        }
+    };
+
+    let output = run(
+        Command::new(&program)
+            .args(["-Y", "verify", "-n", "git", "-f"])
+            .arg(&allowed_signers)
+            .args(["-I", principal, "-s"])
+            .arg(signature_file.path()),
+        signed_data,
+    )?;
+
+    if output.status.success() {
+        Ok(SignatureStatus::Good(principal.to_string()))
+    } else {
+        Ok(SignatureStatus::Bad(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+fn run(command: &mut Command, input: &str) -> Result<std::process::Output, String> {
+    let mut child = match command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) => {
+            return Err(format!(
+                "Failed to start {:?}: {}",
+                command.get_program(),
+                e
+            ))
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(e) = stdin.write_all(input.as_bytes()) {
+            return Err(format!(
+                "Failed to write to {:?}: {}",
+                command.get_program(),
+                e
+            ));
+        }
+    }
+
+    match child.wait_with_output() {
+        Ok(output) => Ok(output),
+        Err(e) => Err(format!("Failed to run {:?}: {}", command.get_program(), e)),
+    }
+}
+
+fn write_temp_file(content: &str) -> Result<NamedTempFile, String> {
+    let mut file = match NamedTempFile::new() {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Failed to create a temporary file: {}", e)),
+    };
+    match file.write_all(content.as_bytes()) {
+        Ok(_) => Ok(file),
+        Err(e) => Err(format!("Failed to write a temporary file: {}", e)),
+    }
+}
+
+fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest).to_string_lossy().to_string(),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GOOD_STATUS: &str = "[GNUPG:] NEWSIG
+[GNUPG:] KEY_CONSIDERED 0123456789ABCDEF 0
+[GNUPG:] GOODSIG 89ABCDEF Dev <dev@example.com>
+[GNUPG:] VALIDSIG 0123456789ABCDEF 2024-03-01 1709251200 0 4 0 22 10 00 0123456789ABCDEF
+";
+
+    fn status(trust: &str) -> String {
+        format!("{}[GNUPG:] {} 0 pgp\n", GOOD_STATUS, trust)
+    }
+
+    #[test]
+    fn trusted_keys_have_good_signatures() {
+        for trust in ["TRUST_MARGINAL", "TRUST_FULLY", "TRUST_ULTIMATE"] {
+            let result = read_gpg_status(&status(trust), DEFAULT_MIN_TRUST_LEVEL);
+            assert!(
+                matches!(result, Some(SignatureStatus::Good(signer)) if signer == "Dev <dev@example.com>")
+            );
+        }
+    }
+
+    #[test]
+    fn untrusted_keys_are_not_good() {
+        for trust in ["TRUST_UNDEFINED", "TRUST_NEVER"] {
+            let result = read_gpg_status(&status(trust), DEFAULT_MIN_TRUST_LEVEL);
+            assert!(matches!(result, Some(SignatureStatus::Untrusted(_))));
+        }
+        assert!(matches!(
+            read_gpg_status(GOOD_STATUS, DEFAULT_MIN_TRUST_LEVEL),
+            Some(SignatureStatus::Untrusted(_))
+        ));
+        //gpg.minTrustLevel can ask for more
+        assert!(matches!(
+            read_gpg_status(&status("TRUST_MARGINAL"), 3),
+            Some(SignatureStatus::Untrusted(_))
+        ));
+    }
+
+    #[test]
+    fn bad_and_invalid_signatures_are_not_good() {
+        let bad = "[GNUPG:] BADSIG 89ABCDEF Dev <dev@example.com>\n";
+        assert!(matches!(
+            read_gpg_status(bad, DEFAULT_MIN_TRUST_LEVEL),
+            Some(SignatureStatus::Bad(_))
+        ));
+
+        let expired =
+            "[GNUPG:] EXPKEYSIG 89ABCDEF Dev <dev@example.com>\n[GNUPG:] TRUST_ULTIMATE 0 pgp\n";
+        assert!(matches!(
+            read_gpg_status(expired, DEFAULT_MIN_TRUST_LEVEL),
+            Some(SignatureStatus::Bad(_))
+        ));
+
+        let without_validsig =
+            "[GNUPG:] GOODSIG 89ABCDEF Dev <dev@example.com>\n[GNUPG:] TRUST_ULTIMATE 0 pgp\n";
+        assert!(matches!(
+            read_gpg_status(without_validsig, DEFAULT_MIN_TRUST_LEVEL),
+            Some(SignatureStatus::Unverifiable(_))
+        ));
+
+        assert!(read_gpg_status("", DEFAULT_MIN_TRUST_LEVEL).is_none());
+    }
+}
@@ -3,6 +3,7 @@ use std::{
     io::{Read, Write},
     path::Path,
 };
+use tempfile::NamedTempFile;
 
 pub fn is_git_repository(path: &str) -> bool {
     let git_path = Path::new(path).join(".git");
@@ -13,8 +14,15 @@ pub fn is_path_exist(path: &str) -> bool {
     Path::new(path).exists()
 }
 
+pub fn is_empty_folder(path: &str) -> bool {
+    match fs::read_dir(path) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => false,
+    }
+}
+
 pub fn read_file_to_string(path: &str) -> Result<String, String> {
-    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+    let mut file = match OpenOptions::new().read(true).open(path) {
         Ok(file) => file,
         Err(e) => return Err(e.to_string()),
     };
@@ -52,18 +60,41 @@ pub fn get_sub_folders(root_path: &str) -> Result<Vec<String>, String> {
     Ok(sub_folders)
 }
 
+//The content goes to a temporary file next to the target, which is then renamed over it
+//Readers see either the old or the new content, never a half-written file
 pub fn write_string_to_file(path: &str, content: String) -> Result<(), String> {
-    let mut file = match OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(path)
-    {
+    let folder = match Path::new(path).parent() {
+        Some(folder) => folder,
+        None => return Err(format!("{} has no parent folder", path)),
+    };
+
+    let mut file = match NamedTempFile::new_in(folder) {
         Ok(file) => file,
         Err(e) => return Err(e.to_string()),
     };
 
-    match file.write_all(content.as_bytes()) {
+    if let Err(e) = file.write_all(content.as_bytes()) {
+        return Err(e.to_string());
+    }
+    if let Err(e) = file.as_file().sync_all() {
+        return Err(e.to_string());
+    }
+
+    match file.persist(path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn copy_file(from: &str, to: &str) -> Result<(), String> {
+    match fs::copy(from, to) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+pub fn rename_file(from: &str, to: &str) -> Result<(), String> {
+    match fs::rename(from, to) {
         Ok(_) => Ok(()),
         Err(e) => Err(e.to_string()),
     }
@@ -89,3 +120,18 @@ pub fn create_file_recursively(path: &str) -> Result<(), String> {
         Err(e) => Err(e.to_string()),
     }
 }
+
+//Resolves relative paths, symlinks and trailing separators, so a folder always has the same key in the config
+//A path that does not exist is returned as it is, e.g. to remove a deleted folder from the watch list
+pub fn get_absolute_path(path: &str) -> String {
+    let absolute_path = match fs::canonicalize(path) {
+        Ok(absolute_path) => absolute_path.to_string_lossy().to_string(),
+        Err(_) => return path.to_string(),
+    };
+
+    //Windows returns verbatim paths such as \\?\C:\repo, which other tools do not accept
+    match absolute_path.strip_prefix(r"\\?\") {
+        Some(stripped) if !stripped.starts_with("UNC") => stripped.to_string(),
+        _ => absolute_path,
+    }
+}
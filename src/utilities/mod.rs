@@ -1,4 +1,4 @@
-pub mod copy_dir_api_wrapper;
+pub mod commit_signer;
 pub mod file_system;
 pub mod git2_api_wrapper;
 pub mod notification_service;
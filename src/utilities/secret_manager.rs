@@ -3,6 +3,7 @@ use keyring::Entry;
 const SERVICE_NAME: &str = "AUTO_GIT_SYNC";
 const SSH_KEY_PATH: &str = "SSH_KEY_PATH";
 const PERSONAL_ACCESS_TOKEN: &str = "PERSONAL_ACCESS_TOKEN";
+const ENCRYPTION_KEY: &str = "ENCRYPTION_KEY";
 
 pub fn set_ssh_key_path(path: &str) -> Result<(), keyring::Error> {
     let entry = Entry::new(SERVICE_NAME, SSH_KEY_PATH)?;
@@ -14,6 +15,11 @@ pub fn set_personal_access_token(token: &str) -> Result<(), keyring::Error> {
     entry.set_password(token)
 }
 
+pub fn set_encryption_key(key: &str) -> Result<(), keyring::Error> {
+    let entry = Entry::new(SERVICE_NAME, ENCRYPTION_KEY)?;
+    entry.set_password(key)
+}
+
 pub fn get_ssh_key_path() -> Result<String, keyring::Error> {
     let entry = Entry::new(SERVICE_NAME, SSH_KEY_PATH)?;
     entry.get_password()
@@ -24,6 +30,11 @@ pub fn get_personal_access_token() -> Result<String, keyring::Error> {
     entry.get_password()
 }
 
+pub fn get_encryption_key() -> Result<String, keyring::Error> {
+    let entry = Entry::new(SERVICE_NAME, ENCRYPTION_KEY)?;
+    entry.get_password()
+}
+
 pub fn delete_ssh_key_path() -> Result<(), keyring::Error> {
     let entry = Entry::new(SERVICE_NAME, SSH_KEY_PATH)?;
     if let Err(e) = entry.get_password() {
@@ -42,3 +53,12 @@ pub fn delete_personal_access_token() -> Result<(), keyring::Error> {
     }
     entry.delete_password()
 }
+
+pub fn delete_encryption_key() -> Result<(), keyring::Error> {
+    let entry = Entry::new(SERVICE_NAME, ENCRYPTION_KEY)?;
+    if let Err(e) = entry.get_password() {
+        println!("Encryption key is not found");
+        return Err(e);
+    }
+    entry.delete_password()
+}